@@ -0,0 +1,223 @@
+//! Append-only record of the changes users make to issues through sentry.mobi, as Sentry's own
+//! activity log can't tell them apart from changes made in Sentry itself.
+
+use std::io::SeekFrom;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+use crate::views::auth::Identity;
+use crate::{Config, Error, Upstream};
+
+/// How many entries the audit log page shows.
+const MAX_ENTRIES: usize = 500;
+
+/// How much of the end of the file is read for the audit log page. Entries are a few hundred
+/// bytes, so this is plenty for `MAX_ENTRIES`, and memory use doesn't grow with the log.
+const MAX_READ: u64 = 1024 * 1024;
+
+/// One line of the audit log.
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: Timestamp,
+    /// base URL of the Sentry instance
+    pub upstream: String,
+    /// email address of the Sentry user, `None` for organization tokens
+    pub user: Option<String>,
+    pub org: String,
+    pub project: String,
+    pub issue_id: String,
+    /// what was changed, e.g. `status` or `assignee`
+    pub field: String,
+    /// as last fetched from Sentry
+    pub old_value: String,
+    pub new_value: String,
+    /// the update as sent to Sentry
+    pub update: serde_json::Value,
+    /// `None` when listening on a Unix socket
+    pub client_ip: Option<IpAddr>,
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    /// so that concurrent entries don't interleave
+    file: Mutex<tokio::fs::File>,
+}
+
+static AUDIT_LOG: OnceLock<AuditLog> = OnceLock::new();
+
+impl AuditLog {
+    /// Open the file at `audit_log` for appending, if set. Failing here is better than failing to
+    /// record a change that has already been made.
+    pub async fn init(config: &Config) -> Result<(), String> {
+        let Some(ref path) = config.audit_log else {
+            return Ok(());
+        };
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options.append(true).create(true);
+        // contains who did what, and from where
+        #[cfg(unix)]
+        options.mode(0o600);
+        let file = options
+            .open(path)
+            .await
+            .map_err(|e| format!("failed to open audit log {}: {e}", path.display()))?;
+
+        let audit_log = AuditLog {
+            path: path.clone(),
+            file: Mutex::new(file),
+        };
+        AUDIT_LOG
+            .set(audit_log)
+            .map_err(|_| "audit log is already initialized".to_owned())
+    }
+
+    /// `None` if no audit log is configured.
+    pub fn get() -> Option<&'static AuditLog> {
+        AUDIT_LOG.get()
+    }
+
+    /// Whether someone logged in to `upstream` as `identity` may browse the audit log, see
+    /// `audit_log_viewers`.
+    pub fn may_view(upstream: &Upstream, identity: &Identity) -> bool {
+        let Some(ref user) = identity.user else {
+            return false;
+        };
+        AuditLog::get().is_some()
+            && Config::get().audit_log_viewers.iter().any(|(host, email)| {
+                host.eq_ignore_ascii_case(upstream.host())
+                    && email.eq_ignore_ascii_case(&user.email)
+            })
+    }
+
+    pub async fn record(&self, entry: &AuditEntry) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await.map_err(Error::AuditLog)?;
+        file.sync_data().await.map_err(Error::AuditLog)
+    }
+
+    /// The most recent entries, newest first.
+    pub async fn recent_entries(&self) -> Result<Vec<AuditEntry>, Error> {
+        let mut file = tokio::fs::File::open(&self.path)
+            .await
+            .map_err(Error::AuditLog)?;
+        let len = file.metadata().await.map_err(Error::AuditLog)?.len();
+        let start = len.saturating_sub(MAX_READ);
+        file.seek(SeekFrom::Start(start))
+            .await
+            .map_err(Error::AuditLog)?;
+
+        let mut contents = Vec::new();
+        // the file may grow while we read it
+        file.take(MAX_READ)
+            .read_to_end(&mut contents)
+            .await
+            .map_err(Error::AuditLog)?;
+        let contents = String::from_utf8_lossy(&contents);
+        let contents = if start > 0 {
+            // starts in the middle of a line
+            contents.split_once('\n').map_or("", |(_, rest)| rest)
+        } else {
+            &contents
+        };
+
+        let mut entries = Vec::new();
+        for line in contents.lines().rev() {
+            if entries.len() == MAX_ENTRIES {
+                break;
+            }
+
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => tracing::warn!("skipping invalid line in audit log: {}", e),
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(issue_id: usize) -> AuditEntry {
+        AuditEntry {
+            timestamp: Timestamp::now(),
+            upstream: "https://sentry.io".to_owned(),
+            user: Some("ann@example.com".to_owned()),
+            org: "acme".to_owned(),
+            project: "backend".to_owned(),
+            issue_id: issue_id.to_string(),
+            field: "status".to_owned(),
+            old_value: "unresolved".to_owned(),
+            new_value: "resolved".to_owned(),
+            update: serde_json::json!({ "status": "resolved" }),
+            client_ip: None,
+        }
+    }
+
+    async fn audit_log(name: &str) -> AuditLog {
+        let path = std::env::temp_dir().join(format!(
+            "sentry-mobi-audit-{name}-{}.jsonl",
+            crate::secret_key::random_id()
+        ));
+        let file = tokio::fs::File::create(&path).await.unwrap();
+        AuditLog {
+            path,
+            file: Mutex::new(file),
+        }
+    }
+
+    #[tokio::test]
+    async fn recent_entries_of_small_log() {
+        let audit_log = audit_log("small").await;
+        audit_log.record(&entry(1)).await.unwrap();
+        audit_log
+            .file
+            .lock()
+            .await
+            .write_all(b"not json\n")
+            .await
+            .unwrap();
+        audit_log.record(&entry(2)).await.unwrap();
+
+        let entries = audit_log.recent_entries().await.unwrap();
+        let issue_ids: Vec<_> = entries.iter().map(|e| e.issue_id.as_str()).collect();
+        assert_eq!(issue_ids, ["2", "1"]);
+
+        std::fs::remove_file(&audit_log.path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recent_entries_of_large_log() {
+        let audit_log = audit_log("large").await;
+        let count = 5000;
+        let mut lines = String::new();
+        for issue_id in 0..count {
+            lines += &serde_json::to_string(&entry(issue_id)).unwrap();
+            lines += "\n";
+        }
+        std::fs::write(&audit_log.path, lines).unwrap();
+        assert!(std::fs::metadata(&audit_log.path).unwrap().len() > MAX_READ);
+
+        let entries = audit_log.recent_entries().await.unwrap();
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0].issue_id, (count - 1).to_string());
+        assert_eq!(
+            entries[MAX_ENTRIES - 1].issue_id,
+            (count - MAX_ENTRIES).to_string()
+        );
+
+        std::fs::remove_file(&audit_log.path).unwrap();
+    }
+}
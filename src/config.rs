@@ -0,0 +1,301 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+
+use crate::upstream::DEFAULT_UPSTREAM;
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:1312";
+const DEFAULT_SESSION_LIFETIME: u64 = 3600;
+
+/// Settings as given on the command line, in environment variables or in the config file. Every
+/// setting is optional here, so that the sources can be merged.
+#[derive(Parser, Deserialize, Default)]
+#[command(version, about)]
+#[serde(default, deny_unknown_fields)]
+struct Args {
+    /// TOML file with any of the settings below, e.g. `listen = "127.0.0.1:8080"`. Command line
+    /// arguments and environment variables take precedence over it.
+    #[arg(long, env = "SENTRY_MOBI_CONFIG")]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+
+    /// Address to listen on, either `host:port` or `unix:/path/to/socket` [default: 0.0.0.0:1312]
+    #[arg(long, env = "LISTEN")]
+    listen: Option<String>,
+
+    /// Path prefix to serve under, e.g. `/sentry` when the reverse proxy forwards
+    /// `https://tools.example.com/sentry/` without stripping the prefix [default: none]
+    #[arg(long, env = "BASE_PATH")]
+    base_path: Option<String>,
+
+    /// PEM file with the certificate chain to serve HTTPS with. Only for TCP addresses
+    #[arg(long, env = "TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key for `--tls-cert`
+    #[arg(long, env = "TLS_KEY")]
+    tls_key: Option<PathBuf>,
+
+    /// Serve HTTPS with a self-signed certificate, e.g. for use on a LAN. If `--tls-cert` and
+    /// `--tls-key` are given, the certificate is stored there so that browsers only have to trust
+    /// it once
+    #[arg(long, env = "TLS_SELF_SIGNED")]
+    tls_self_signed: Option<bool>,
+
+    /// Log users out after this many seconds of inactivity [default: 3600]
+    #[arg(long, env = "SESSION_LIFETIME")]
+    session_lifetime: Option<u64>,
+
+    /// Whether session cookies are only sent over HTTPS. Defaults to true in release builds and
+    /// when serving HTTPS. Turn off when serving plain HTTP, e.g. on a laptop
+    #[arg(long, env = "SECURE_COOKIES")]
+    secure_cookies: Option<bool>,
+
+    /// `memory`, or `file:<directory>` to keep sessions across restarts [default: memory]
+    #[arg(long, env = "SESSION_STORE")]
+    session_store: Option<String>,
+
+    /// The Sentry instance to use. Several instances can be given separated by spaces, users
+    /// pick one when logging in [default: https://sentry.io]
+    #[arg(long, env = "SENTRY_URL")]
+    sentry_url: Option<String>,
+
+    /// Base64-encoded 32 byte key to encrypt API tokens in sessions with
+    #[arg(long, env = "SECRET_KEY", hide_env_values = true)]
+    secret_key: Option<String>,
+
+    /// File to load the secret key from, created if it doesn't exist
+    #[arg(long, env = "SECRET_KEY_FILE")]
+    secret_key_file: Option<PathBuf>,
+
+    /// Client ID of a Sentry OAuth application, to let users log in without an API token
+    #[arg(long, env = "SENTRY_OAUTH_CLIENT_ID")]
+    oauth_client_id: Option<String>,
+
+    /// Client secret of the OAuth application
+    #[arg(long, env = "SENTRY_OAUTH_CLIENT_SECRET", hide_env_values = true)]
+    oauth_client_secret: Option<String>,
+
+    /// Redirect URI of the OAuth application, if it has several
+    #[arg(long, env = "SENTRY_OAUTH_REDIRECT_URI")]
+    oauth_redirect_uri: Option<String>,
+
+    /// File to append a JSON line to for every change made to issues, for auditing
+    #[arg(long, env = "AUDIT_LOG")]
+    audit_log: Option<PathBuf>,
+
+    /// Sentry users that may browse the audit log, as host of the instance and email address,
+    /// e.g. `sentry.io/ann@example.com`. Several can be given separated by spaces
+    #[arg(long, env = "AUDIT_LOG_VIEWERS")]
+    audit_log_viewers: Option<String>,
+
+    /// Format of log lines, `json` is easier to process for log collectors [default: text]
+    #[arg(long, env = "LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+}
+
+impl Args {
+    /// Fill in settings that are not set in `self` from `other`.
+    fn or(self, other: Args) -> Args {
+        Args {
+            config: self.config.or(other.config),
+            listen: self.listen.or(other.listen),
+            base_path: self.base_path.or(other.base_path),
+            tls_cert: self.tls_cert.or(other.tls_cert),
+            tls_key: self.tls_key.or(other.tls_key),
+            tls_self_signed: self.tls_self_signed.or(other.tls_self_signed),
+            session_lifetime: self.session_lifetime.or(other.session_lifetime),
+            secure_cookies: self.secure_cookies.or(other.secure_cookies),
+            session_store: self.session_store.or(other.session_store),
+            sentry_url: self.sentry_url.or(other.sentry_url),
+            secret_key: self.secret_key.or(other.secret_key),
+            secret_key_file: self.secret_key_file.or(other.secret_key_file),
+            oauth_client_id: self.oauth_client_id.or(other.oauth_client_id),
+            oauth_client_secret: self.oauth_client_secret.or(other.oauth_client_secret),
+            oauth_redirect_uri: self.oauth_redirect_uri.or(other.oauth_redirect_uri),
+            audit_log: self.audit_log.or(other.audit_log),
+            audit_log_viewers: self.audit_log_viewers.or(other.audit_log_viewers),
+            log_format: self.log_format.or(other.log_format),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+pub enum Listen {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+/// Where the certificate for HTTPS comes from, see `tls::load`.
+pub enum Tls {
+    Files {
+        cert: PathBuf,
+        key: PathBuf,
+    },
+    /// stored in the files if they are given, otherwise regenerated on every start
+    SelfSigned {
+        files: Option<(PathBuf, PathBuf)>,
+    },
+}
+
+/// The runtime configuration, with defaults applied.
+pub struct Config {
+    pub listen: Listen,
+    /// empty, or starting with a slash and without trailing slash, see `routes::url`
+    pub base_path: String,
+    pub tls: Option<Tls>,
+    pub session_lifetime: u64,
+    pub secure_cookies: bool,
+    pub session_store: String,
+    pub sentry_url: String,
+    pub secret_key: Option<String>,
+    pub secret_key_file: Option<PathBuf>,
+    pub oauth_client_id: Option<String>,
+    pub oauth_client_secret: Option<String>,
+    pub oauth_redirect_uri: Option<String>,
+    pub audit_log: Option<PathBuf>,
+    /// (host of the Sentry instance, email address)
+    pub audit_log_viewers: Vec<(String, String)>,
+    pub log_format: LogFormat,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+impl Config {
+    /// Parse the command line (exiting on `--help` or invalid arguments), environment variables
+    /// and config file.
+    pub fn init() -> Result<&'static Config, String> {
+        let args = Args::parse();
+        let args = match args.config.clone() {
+            Some(path) => args.or(read_file(&path)?),
+            None => args,
+        };
+
+        CONFIG
+            .set(Config::from_args(args)?)
+            .map_err(|_| "config is already initialized".to_owned())?;
+        Ok(Config::get())
+    }
+
+    /// The default configuration, for tests that need `Config::get` to work.
+    #[cfg(test)]
+    pub fn init_for_tests() -> &'static Config {
+        CONFIG.get_or_init(|| Config::from_args(Args::default()).unwrap())
+    }
+
+    fn from_args(args: Args) -> Result<Config, String> {
+        let listen = args.listen.as_deref().unwrap_or(DEFAULT_LISTEN);
+        let listen = match listen.strip_prefix("unix:") {
+            Some(path) => Listen::Unix(path.into()),
+            None => Listen::Tcp(
+                listen
+                    .parse()
+                    .map_err(|e| format!("invalid listen address {listen:?}: {e}"))?,
+            ),
+        };
+
+        let base_path = args
+            .base_path
+            .as_deref()
+            .unwrap_or_default()
+            .trim_matches('/');
+        let base_path = if base_path.is_empty() {
+            String::new()
+        } else {
+            format!("/{base_path}")
+        };
+
+        let files = match (args.tls_cert, args.tls_key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => return Err("--tls-cert and --tls-key must be given together".to_owned()),
+        };
+        let tls = match (args.tls_self_signed.unwrap_or(false), files) {
+            (true, files) => Some(Tls::SelfSigned { files }),
+            (false, Some((cert, key))) => Some(Tls::Files { cert, key }),
+            (false, None) => None,
+        };
+        if tls.is_some() && matches!(listen, Listen::Unix(_)) {
+            return Err("TLS can only be used with TCP addresses".to_owned());
+        }
+
+        Ok(Config {
+            listen,
+            base_path,
+            secure_cookies: args
+                .secure_cookies
+                .unwrap_or(tls.is_some() || !cfg!(debug_assertions)),
+            tls,
+            session_lifetime: args.session_lifetime.unwrap_or(DEFAULT_SESSION_LIFETIME),
+            session_store: args.session_store.unwrap_or_else(|| "memory".to_owned()),
+            sentry_url: args
+                .sentry_url
+                .unwrap_or_else(|| DEFAULT_UPSTREAM.to_owned()),
+            secret_key: args.secret_key,
+            secret_key_file: args.secret_key_file,
+            oauth_client_id: args.oauth_client_id,
+            oauth_client_secret: args.oauth_client_secret,
+            oauth_redirect_uri: args.oauth_redirect_uri,
+            audit_log: args.audit_log,
+            audit_log_viewers: args
+                .audit_log_viewers
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(parse_audit_log_viewer)
+                .collect::<Result<_, _>>()?,
+            log_format: args.log_format.unwrap_or(LogFormat::Text),
+        })
+    }
+
+    pub fn get() -> &'static Config {
+        CONFIG.get().expect("config is loaded at startup")
+    }
+}
+
+/// `host/email`. Email addresses alone are not enough, as anyone may be able to sign up with any
+/// address on some instance.
+fn parse_audit_log_viewer(viewer: &str) -> Result<(String, String), String> {
+    match viewer.split_once('/') {
+        Some((host, email)) if !host.is_empty() && email.contains('@') => {
+            Ok((host.to_owned(), email.to_owned()))
+        }
+        _ => Err(format!(
+            "invalid audit log viewer {viewer:?}, expected e.g. 'sentry.io/ann@example.com'"
+        )),
+    }
+}
+
+fn read_file(path: &Path) -> Result<Args, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    toml::from_str(&contents).map_err(|e| format!("invalid config file {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn audit_log_viewer() {
+        assert_eq!(
+            parse_audit_log_viewer("sentry.example.com:9000/ann@example.com").unwrap(),
+            (
+                "sentry.example.com:9000".to_owned(),
+                "ann@example.com".to_owned()
+            )
+        );
+        // the email address alone doesn't say which instance vouches for it
+        assert!(parse_audit_log_viewer("ann@example.com").is_err());
+        assert!(parse_audit_log_viewer("/ann@example.com").is_err());
+        assert!(parse_audit_log_viewer("sentry.io/").is_err());
+    }
+}
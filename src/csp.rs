@@ -0,0 +1,46 @@
+//! Security headers for all responses. As we display sensitive event data, pages must not be able
+//! to run or load anything that isn't ours.
+
+use axum::extract::Request;
+use axum::http::header::{
+    CONTENT_SECURITY_POLICY, REFERRER_POLICY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::secret_key::random_id;
+
+tokio::task_local! {
+    static NONCE: String;
+}
+
+/// The nonce that inline styles of the current response need to carry. Only available while a
+/// request is handled by `headers`.
+pub fn nonce() -> String {
+    NONCE.try_with(|nonce| nonce.clone()).unwrap_or_default()
+}
+
+/// Middleware that adds the headers, with a fresh nonce per request.
+pub async fn headers(request: Request, next: Next) -> Response {
+    let nonce = random_id();
+    let mut response = NONCE.scope(nonce.clone(), next.run(request)).await;
+
+    // avatars are loaded from Sentry or Gravatar, and picocss uses data: URLs for icons
+    let policy = format!(
+        "default-src 'none'; script-src 'self'; style-src 'self' 'nonce-{nonce}'; \
+         img-src 'self' data: https:; connect-src 'self'; form-action 'self'; \
+         frame-ancestors 'none'; base-uri 'none'"
+    );
+
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_SECURITY_POLICY,
+        HeaderValue::from_str(&policy).expect("nonce is base64"),
+    );
+    headers.insert(X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+    headers.insert(REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+
+    response
+}
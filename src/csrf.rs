@@ -0,0 +1,242 @@
+//! Protection against cross-site request forgery: every POST needs to prove that it comes from a
+//! page we rendered, by sending the session's CSRF token along.
+
+use axum::body::{to_bytes, Body};
+use axum::extract::Request;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tower_sessions::Session;
+
+use crate::secret_key::random_id;
+use crate::Error;
+
+/// Form field that forms rendered by us contain.
+pub const FORM_FIELD: &str = "csrf_token";
+
+/// Header that htmx sends, see `hx-headers` in `wrap_template`.
+pub const HEADER: &str = "x-csrf-token";
+
+const SESSION_CSRF_KEY: &str = "csrf_token";
+
+/// Forms are small, anything bigger is not from us.
+const MAX_FORM_SIZE: usize = 64 * 1024;
+
+tokio::task_local! {
+    static CSRF_TOKEN: String;
+}
+
+/// The current session's token, to be embedded into forms. Only available while a request is
+/// handled by `verify`.
+pub fn token() -> String {
+    CSRF_TOKEN
+        .try_with(|token| token.clone())
+        .unwrap_or_default()
+}
+
+/// Middleware that rejects unsafe requests without a valid token, and makes the token available
+/// to templates through `token`.
+pub async fn verify(request: Request, next: Next) -> Response {
+    let Some(session) = request.extensions().get::<Session>().cloned() else {
+        return Error::InvalidCsrfToken.into_response();
+    };
+
+    let token = match session_token(&session).await {
+        Ok(token) => token,
+        Err(e) => return e.into_response(),
+    };
+
+    let request = if is_safe(request.method()) {
+        request
+    } else {
+        match check_request(request, &token).await {
+            Some(request) => request,
+            None => {
+                tracing::debug!("rejected request with missing or invalid csrf token");
+                return CSRF_TOKEN.sync_scope(token, || Error::InvalidCsrfToken.into_response());
+            }
+        }
+    };
+
+    CSRF_TOKEN.scope(token, next.run(request)).await
+}
+
+async fn session_token(session: &Session) -> Result<String, Error> {
+    if let Some(token) = session.get(SESSION_CSRF_KEY).await? {
+        return Ok(token);
+    }
+
+    let token = random_id();
+    session.insert(SESSION_CSRF_KEY, &token).await?;
+    Ok(token)
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Look for the token in the header, then in the form body. As the body has to be read for that,
+/// the request is reassembled and returned if the token is valid.
+async fn check_request(request: Request, token: &str) -> Option<Request> {
+    if let Some(header) = request.headers().get(HEADER) {
+        return (header.as_bytes() == token.as_bytes()).then_some(request);
+    }
+
+    let is_form = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return None;
+    }
+
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, MAX_FORM_SIZE).await.ok()?;
+
+    let fields: Vec<(String, String)> = serde_urlencoded::from_bytes(&body).ok()?;
+    let valid = fields
+        .iter()
+        .any(|(key, value)| key == FORM_FIELD && value == token);
+
+    valid.then(|| Request::from_parts(parts, Body::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+    use axum::Router;
+    use reqwest::header::{COOKIE, SET_COOKIE};
+    use reqwest::StatusCode;
+    use tower_sessions::{MemoryStore, SessionManagerLayer};
+
+    use super::*;
+    use crate::test_util::serve;
+    use crate::Config;
+
+    const FORM: &str = "application/x-www-form-urlencoded";
+
+    /// Serve the middleware in front of a route that returns the token on GET and echoes the
+    /// body on POST, and return its URL.
+    async fn serve_verified() -> String {
+        Config::init_for_tests();
+
+        let router = Router::new()
+            .route(
+                "/",
+                get(|| async { token() }).post(|body: String| async { body }),
+            )
+            .layer(axum::middleware::from_fn(verify))
+            .layer(SessionManagerLayer::new(MemoryStore::default()).with_secure(false));
+        format!("{}/", serve(router).await)
+    }
+
+    /// Start a session, and return its cookie and token.
+    async fn session(url: &str) -> (String, String) {
+        let response = reqwest::get(url).await.unwrap();
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        let cookie = cookie.split(';').next().unwrap().to_owned();
+        let token = response.text().await.unwrap();
+        assert!(!token.is_empty());
+        (cookie, token)
+    }
+
+    async fn post(
+        url: &str,
+        cookie: &str,
+        headers: &[(&str, &str)],
+        body: String,
+    ) -> reqwest::Response {
+        let mut request = reqwest::Client::new().post(url).header(COOKIE, cookie);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.body(body).send().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn valid_form_body_is_passed_on() {
+        let url = serve_verified().await;
+        let (cookie, token) = session(&url).await;
+
+        let body = format!("status=resolved&{FORM_FIELD}={token}&note=a%20b");
+        let response = post(&url, &cookie, &[("content-type", FORM)], body.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn valid_header() {
+        let url = serve_verified().await;
+        let (cookie, token) = session(&url).await;
+
+        let headers = [
+            (HEADER, token.as_str()),
+            ("content-type", "application/json"),
+        ];
+        let response = post(&url, &cookie, &headers, "{}".to_owned()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "{}");
+    }
+
+    #[tokio::test]
+    async fn missing_token() {
+        let url = serve_verified().await;
+        let (cookie, _) = session(&url).await;
+
+        let response = post(
+            &url,
+            &cookie,
+            &[("content-type", FORM)],
+            "status=resolved".into(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = post(&url, &cookie, &[], String::new()).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn wrong_token_in_form() {
+        let url = serve_verified().await;
+        let (cookie, _) = session(&url).await;
+
+        let body = format!("status=resolved&{FORM_FIELD}=guessed");
+        let response = post(&url, &cookie, &[("content-type", FORM)], body).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn wrong_header_is_not_rescued_by_form() {
+        let url = serve_verified().await;
+        let (cookie, token) = session(&url).await;
+
+        let headers = [(HEADER, "guessed"), ("content-type", FORM)];
+        let response = post(&url, &cookie, &headers, format!("{FORM_FIELD}={token}")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn token_in_other_content_type() {
+        let url = serve_verified().await;
+        let (cookie, token) = session(&url).await;
+
+        let headers = [("content-type", "text/plain")];
+        let response = post(&url, &cookie, &headers, format!("{FORM_FIELD}={token}")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn token_of_other_session() {
+        let url = serve_verified().await;
+        let (_, token) = session(&url).await;
+        let (other_cookie, other_token) = session(&url).await;
+        assert_ne!(token, other_token);
+
+        let body = format!("{FORM_FIELD}={token}");
+        let response = post(&url, &other_cookie, &[("content-type", FORM)], body).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
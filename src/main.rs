@@ -1,46 +1,189 @@
 use std::net::SocketAddr;
+use std::path::Path;
 
 use time::Duration;
 
 use axum::{
-    http::StatusCode,
+    http::{
+        header::{CACHE_CONTROL, RETRY_AFTER},
+        HeaderMap, HeaderValue, StatusCode,
+    },
     response::{IntoResponse, Redirect, Response},
     Router,
 };
+use maud::Markup;
 use memory_serve::{load_assets, MemoryServe};
-use tower_sessions::{Expiry, MemoryStore, SessionManagerLayer};
+use tower_sessions::cookie::SameSite;
+use tower_sessions::{Expiry, SessionManagerLayer};
 
+mod audit;
+mod config;
+mod csp;
+mod csrf;
+mod metrics;
+mod oauth;
 mod routes;
+mod secret_key;
+mod sentry_api;
+mod session_store;
+#[cfg(test)]
+mod test_util;
+mod tls;
+mod upstream;
 mod views;
 
+pub(crate) use audit::AuditLog;
+pub(crate) use config::Config;
+use config::{Listen, LogFormat, Tls};
+pub(crate) use oauth::OAuthApp;
+use routes::{url, Index};
+pub(crate) use secret_key::SecretKey;
+pub(crate) use sentry_api::SentryApi;
+use session_store::SessionBackend;
+pub(crate) use upstream::Upstream;
 pub(crate) use views::auth::SentryToken;
+use views::helpers::{html, login_form, wrap_template, Html, LayoutOptions};
 
 #[tokio::main]
 async fn main() {
+    let config = match Config::init() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt().init(),
+        LogFormat::Json => tracing_subscriber::fmt().json().init(),
+    }
+
     let static_files = MemoryServe::new(load_assets!("static")).into_router();
 
-    tracing_subscriber::fmt::init();
+    if let Err(e) = SecretKey::init(config) {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+
+    if let Err(e) = AuditLog::init(config).await {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
 
-    let session_store = MemoryStore::default();
+    let session_store = match SessionBackend::from_config(&config.session_store).await {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    session_store.spawn_cleanup();
 
     let session_layer = SessionManagerLayer::new(session_store)
-        .with_secure(!cfg!(debug_assertions))
-        .with_expiry(Expiry::OnInactivity(Duration::seconds(3600)));
+        .with_secure(config.secure_cookies)
+        // the OAuth callback is a cross-site navigation from Sentry and needs the session
+        .with_same_site(SameSite::Lax)
+        .with_path(if config.base_path.is_empty() {
+            "/".to_owned()
+        } else {
+            config.base_path.clone()
+        })
+        .with_expiry(Expiry::OnInactivity(Duration::seconds(
+            config.session_lifetime.try_into().unwrap_or(i64::MAX),
+        )));
 
-    let app = Router::new()
-        .merge(static_files)
-        .merge(routes::get_router())
-        .layer(session_layer);
+    let app = Router::new().merge(static_files).merge(
+        routes::get_router()
+            .layer(axum::middleware::from_fn(csrf::verify))
+            .layer(axum::middleware::from_fn(
+                views::helpers::retarget_htmx_errors,
+            ))
+            .route_layer(axum::middleware::from_fn(metrics::track_requests)),
+    );
 
-    let addr = "0.0.0.0:1312";
+    let app = if config.base_path.is_empty() {
+        app
+    } else {
+        Router::new().nest(&config.base_path, app).route(
+            &format!("{}/", config.base_path),
+            axum::routing::get(|| async { Redirect::permanent(&url(Index)) }),
+        )
+    };
+
+    let app = app
+        .layer(session_layer)
+        .merge(routes::get_monitoring_router())
+        .layer(axum::middleware::from_fn(csp::headers));
+
+    for upstream in Upstream::all() {
+        tracing::info!("using sentry at {}", upstream.base_url());
+    }
+
+    let result = match (&config.listen, &config.tls) {
+        (Listen::Tcp(addr), None) => serve_tcp(app, *addr).await,
+        (Listen::Tcp(addr), Some(tls)) => serve_tls(app, *addr, tls).await,
+        (Listen::Unix(path), _) => serve_unix(app, path).await,
+    };
+
+    if let Err(e) = result {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn serve_tcp(app: Router, addr: SocketAddr) -> std::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
     tracing::info!("listening on {}", addr);
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     axum::serve(
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
     .await
-    .unwrap();
+}
+
+/// HTTPS without a reverse proxy in front, see `tls::load`.
+async fn serve_tls(app: Router, addr: SocketAddr, tls: &Tls) -> std::io::Result<()> {
+    let rustls_config = match tls::load(tls).await {
+        Ok(rustls_config) => rustls_config,
+        Err(e) => return Err(std::io::Error::other(e)),
+    };
+
+    tracing::info!("listening on https://{}", addr);
+    axum_server::bind_rustls(addr, rustls_config)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+}
+
+/// For running behind a reverse proxy on the same host. `axum::serve` only supports TCP, so this
+/// drives hyper directly.
+async fn serve_unix(app: Router, path: &Path) -> std::io::Result<()> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::service::TowerToHyperService;
+
+    // left behind by a previous run
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)?;
+    tracing::info!("listening on {}", path.display());
+
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let service = TowerToHyperService::new(app.clone());
+
+        tokio::spawn(async move {
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(socket), service)
+                .await
+            {
+                tracing::debug!("failed to serve connection: {}", e);
+            }
+        });
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -49,25 +192,171 @@ enum Error {
     Session(#[from] tower_sessions::session::Error),
     #[error("no token found")]
     NeedsAuth { redirect_to: Option<String> },
+    #[error("sentry rejected the API token")]
+    Unauthorized { redirect_to: Option<String> },
+    #[error("the form has expired")]
+    InvalidCsrfToken,
+    #[error("{0}")]
+    InvalidForm(&'static str),
+    #[error("the API token is not allowed to access this")]
+    Forbidden,
+    #[error("you are not allowed to view this")]
+    AccessDenied,
+    #[error("not found in sentry")]
+    NotFound,
+    #[error("rate-limited by sentry")]
+    RateLimited { retry_after: Option<u64> },
+    #[error("sentry api responded with {0}")]
+    Upstream(StatusCode),
     #[error("failed to fetch from sentry api: {0}")]
     Reqwest(#[from] reqwest::Error),
+    #[error("failed to parse sentry api response: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("failed to access the audit log: {0}")]
+    AuditLog(std::io::Error),
+}
+
+impl Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::NeedsAuth { .. } => StatusCode::SEE_OTHER,
+            Error::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
+            Error::Forbidden | Error::AccessDenied | Error::InvalidCsrfToken => {
+                StatusCode::FORBIDDEN
+            }
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::InvalidForm(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Error::Upstream(_) => StatusCode::BAD_GATEWAY,
+            Error::Reqwest(e) if !e.is_decode() => StatusCode::BAD_GATEWAY,
+            Error::Reqwest(_) | Error::Json(_) | Error::Session(_) | Error::AuditLog(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Explanation of what went wrong and what the user can do about it.
+    fn help(&self) -> Markup {
+        match self {
+            Error::Unauthorized { redirect_to } => html! {
+                p { "the token may have been revoked or expired. log in again:" }
+                (login_form(redirect_to.as_deref(), None))
+            },
+            Error::Forbidden => html! {
+                p {
+                    "the token may be missing scopes, or you are not a member of this "
+                    "organization or team."
+                }
+            },
+            Error::AccessDenied => html! {
+                p { "ask whoever runs this site to add your email address to the audit log viewers." }
+            },
+            Error::InvalidCsrfToken => html! {
+                p { "go back, reload the page and try again." }
+            },
+            Error::InvalidForm(_) => html! {
+                p { "go back and try again." }
+            },
+            Error::NotFound => html! {
+                p { "sentry doesn't know about this organization, project or issue. check the URL for typos." }
+            },
+            Error::RateLimited { retry_after } => html! {
+                p {
+                    "too many requests were made with your token. try again "
+                    @if let Some(retry_after) = retry_after {
+                        "in " (retry_after) " seconds."
+                    } @else {
+                        "later."
+                    }
+                }
+            },
+            _ => html! {
+                p { code { (self) } }
+            },
+        }
+    }
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
         match self {
             Error::NeedsAuth { redirect_to } => {
-                if let Some(redirect_to) = redirect_to {
-                    Redirect::to(&format!("/?redirect_to={redirect_to}")).into_response()
+                if let Some(redirect_to) = redirect_to.filter(|target| routes::is_own_url(target)) {
+                    let query = serde_urlencoded::to_string([("redirect_to", redirect_to)])
+                        .expect("params are strings");
+                    Redirect::to(&format!("{}?{query}", url(Index))).into_response()
                 } else {
-                    Redirect::to("/").into_response()
+                    Redirect::to(&url(Index)).into_response()
                 }
             }
             _ => {
-                let s = self.to_string();
-                tracing::error!("error while serving request: {:?}", s);
-                (StatusCode::INTERNAL_SERVER_ERROR, s).into_response()
+                let status = self.status_code();
+                if status.is_server_error() {
+                    tracing::error!("error while serving request: {:?}", self.to_string());
+                }
+
+                let mut headers = HeaderMap::new();
+                headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+                if let Error::RateLimited {
+                    retry_after: Some(retry_after),
+                } = self
+                {
+                    headers.insert(RETRY_AFTER, HeaderValue::from(retry_after));
+                }
+
+                let body = wrap_template(
+                    LayoutOptions {
+                        title: "error".to_owned(),
+                        ..Default::default()
+                    },
+                    html! {
+                        h2 { (self) }
+                        (self.help())
+                        p { a href=(url(Index)) { "back to start" } }
+                    },
+                );
+
+                (status, headers, Html(body)).into_response()
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::LOCATION;
+
+    use super::*;
+
+    fn needs_auth_location(redirect_to: Option<&str>) -> String {
+        Config::init_for_tests();
+        let response = Error::NeedsAuth {
+            redirect_to: redirect_to.map(str::to_owned),
+        }
+        .into_response();
+
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        response.headers()[LOCATION].to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn needs_auth_redirects_back_to_own_page() {
+        assert_eq!(
+            needs_auth_location(Some("/acme/backend?query=is%3Aunresolved")),
+            "/?redirect_to=%2Facme%2Fbackend%3Fquery%3Dis%253Aunresolved"
+        );
+    }
+
+    #[test]
+    fn needs_auth_ignores_foreign_target() {
+        for target in [
+            "//evil.example",
+            "/\\evil.example",
+            "https://evil.example",
+            "/\t/evil.example",
+        ] {
+            assert_eq!(needs_auth_location(Some(target)), url(Index), "{target:?}");
+        }
+        assert_eq!(needs_auth_location(None), url(Index));
+    }
+}
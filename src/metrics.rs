@@ -0,0 +1,270 @@
+//! Liveness probe and Prometheus metrics. Both are served outside of the session layer, so that
+//! probes don't create sessions, and outside of the base path, so that a reverse proxy forwarding
+//! only the base path doesn't expose them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use axum::extract::{MatchedPath, Request};
+use axum::http::header::CONTENT_TYPE;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use time::OffsetDateTime;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static REQUESTS: Family<u64> = Family::new(&["method", "route", "status"]);
+static REQUEST_DURATION: Family<Histogram> = Family::new(&["route"]);
+static UPSTREAM_REQUESTS: Family<u64> = Family::new(&["endpoint", "status"]);
+static UPSTREAM_DURATION: Family<Histogram> = Family::new(&["endpoint"]);
+static SESSIONS_CREATED: AtomicU64 = AtomicU64::new(0);
+static SESSIONS_DELETED: AtomicU64 = AtomicU64::new(0);
+/// expiry date per session ID, for counting the sessions that are still valid. Sessions that
+/// expire are not deleted from the stores right away, so created minus deleted is not that.
+static SESSION_EXPIRIES: Mutex<Option<HashMap<String, OffsetDateTime>>> = Mutex::new(None);
+static CLIENT_CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static CLIENT_CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+/// Values of one metric, keyed by their label values.
+struct Family<T> {
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, T>>,
+}
+
+impl<T: Default> Family<T> {
+    const fn new(labels: &'static [&'static str]) -> Self {
+        Family {
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn with(&self, label_values: &[&str], f: impl FnOnce(&mut T)) {
+        let key = label_values.iter().map(|value| value.to_string()).collect();
+        f(self.values.lock().unwrap().entry(key).or_default());
+    }
+
+    /// `{a="x",b="y"}`, with `extra` appended for histogram buckets.
+    fn format_labels(&self, label_values: &[String], extra: Option<(&str, &str)>) -> String {
+        let labels = self
+            .labels
+            .iter()
+            .copied()
+            .zip(label_values.iter().map(String::as_str))
+            .chain(extra)
+            .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+            .collect::<Vec<_>>();
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// not cumulative, unlike in the exposition format
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Middleware that counts requests and their latency per route. Has to be added with
+/// `Router::route_layer`, so that the route is known.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let Some(route) = request.extensions().get::<MatchedPath>().cloned() else {
+        return next.run(request).await;
+    };
+    let method = request.method().clone();
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    let elapsed = start.elapsed().as_secs_f64();
+    REQUESTS.with(
+        &[method.as_str(), route.as_str(), response.status().as_str()],
+        |count| *count += 1,
+    );
+    REQUEST_DURATION.with(&[route.as_str()], |histogram| histogram.observe(elapsed));
+    response
+}
+
+/// Record a request to the Sentry API. `status` is `None` if no response was received.
+pub fn track_upstream_request(endpoint: &str, status: Option<u16>, start: Instant) {
+    let elapsed = start.elapsed().as_secs_f64();
+    let status = status.map_or_else(|| "error".to_owned(), |status| status.to_string());
+    UPSTREAM_REQUESTS.with(&[endpoint, &status], |count| *count += 1);
+    UPSTREAM_DURATION.with(&[endpoint], |histogram| histogram.observe(elapsed));
+}
+
+pub fn track_session_created(id: &str, expiry: OffsetDateTime) {
+    SESSIONS_CREATED.fetch_add(1, Ordering::Relaxed);
+    track_session_saved(id, expiry);
+}
+
+/// Record the current expiry date of a session, which is extended on every save. Also used for
+/// sessions found on disk after a restart.
+pub fn track_session_saved(id: &str, expiry: OffsetDateTime) {
+    with_session_expiries(|expiries| expiries.insert(id.to_owned(), expiry));
+}
+
+pub fn track_session_deleted(id: &str) {
+    SESSIONS_DELETED.fetch_add(1, Ordering::Relaxed);
+    with_session_expiries(|expiries| expiries.remove(id));
+}
+
+/// How many sessions have not expired yet. Forgets the others.
+fn active_sessions() -> usize {
+    let now = OffsetDateTime::now_utc();
+    with_session_expiries(|expiries| {
+        expiries.retain(|_, expiry| *expiry > now);
+        expiries.len()
+    })
+}
+
+fn with_session_expiries<R>(f: impl FnOnce(&mut HashMap<String, OffsetDateTime>) -> R) -> R {
+    f(SESSION_EXPIRIES
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new))
+}
+
+/// Record a lookup in the per-token HTTP client cache, see `views::auth::client`.
+pub fn track_client_cache(hit: bool) {
+    let counter = if hit {
+        &CLIENT_CACHE_HITS
+    } else {
+        &CLIENT_CACHE_MISSES
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub async fn healthz(_: crate::routes::Healthz) -> &'static str {
+    "ok\n"
+}
+
+pub async fn metrics(_: crate::routes::Metrics) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        render(),
+    )
+}
+
+/// All metrics in the Prometheus text exposition format.
+fn render() -> String {
+    let mut out = String::new();
+
+    write_counters(
+        &mut out,
+        "sentry_mobi_http_requests_total",
+        "Requests handled, by route and response status.",
+        &REQUESTS,
+    );
+    write_histograms(
+        &mut out,
+        "sentry_mobi_http_request_duration_seconds",
+        "Time taken to handle requests, by route.",
+        &REQUEST_DURATION,
+    );
+    write_counters(
+        &mut out,
+        "sentry_mobi_upstream_requests_total",
+        "Requests made to the Sentry API, by endpoint and response status.",
+        &UPSTREAM_REQUESTS,
+    );
+    write_histograms(
+        &mut out,
+        "sentry_mobi_upstream_request_duration_seconds",
+        "Time taken by the Sentry API to respond, by endpoint.",
+        &UPSTREAM_DURATION,
+    );
+    write_header(
+        &mut out,
+        "sentry_mobi_sessions",
+        "Sessions that have not expired.",
+        "gauge",
+    );
+    writeln!(out, "sentry_mobi_sessions {}", active_sessions()).unwrap();
+    write_counter(
+        &mut out,
+        "sentry_mobi_sessions_created_total",
+        "Sessions created.",
+        &SESSIONS_CREATED,
+    );
+    write_counter(
+        &mut out,
+        "sentry_mobi_sessions_deleted_total",
+        "Sessions deleted, e.g. on logout. Expired sessions are not counted.",
+        &SESSIONS_DELETED,
+    );
+    write_counter(
+        &mut out,
+        "sentry_mobi_client_cache_hits_total",
+        "Sentry API clients reused from the cache.",
+        &CLIENT_CACHE_HITS,
+    );
+    write_counter(
+        &mut out,
+        "sentry_mobi_client_cache_misses_total",
+        "Sentry API clients that had to be created.",
+        &CLIENT_CACHE_MISSES,
+    );
+
+    out
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, counter: &AtomicU64) {
+    write_header(out, name, help, "counter");
+    writeln!(out, "{name} {}", counter.load(Ordering::Relaxed)).unwrap();
+}
+
+fn write_counters(out: &mut String, name: &str, help: &str, family: &Family<u64>) {
+    write_header(out, name, help, "counter");
+    for (label_values, count) in family.values.lock().unwrap().iter() {
+        let labels = family.format_labels(label_values, None);
+        writeln!(out, "{name}{labels} {count}").unwrap();
+    }
+}
+
+fn write_histograms(out: &mut String, name: &str, help: &str, family: &Family<Histogram>) {
+    write_header(out, name, help, "histogram");
+    for (label_values, histogram) in family.values.lock().unwrap().iter() {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let labels = family.format_labels(label_values, Some(("le", &bound.to_string())));
+            writeln!(out, "{name}_bucket{labels} {cumulative}").unwrap();
+        }
+        let labels = family.format_labels(label_values, Some(("le", "+Inf")));
+        writeln!(out, "{name}_bucket{labels} {}", histogram.count).unwrap();
+
+        let labels = family.format_labels(label_values, None);
+        writeln!(out, "{name}_sum{labels} {}", histogram.sum).unwrap();
+        writeln!(out, "{name}_count{labels} {}", histogram.count).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+use std::sync::OnceLock;
+
+use jiff::Timestamp;
+use serde::Deserialize;
+
+use crate::{Config, Error, Upstream};
+
+/// Scopes requested when logging in through OAuth. Enough to view issues and change their status.
+const SCOPES: &str = "org:read project:read event:read event:write";
+
+/// Credentials of an OAuth application registered in Sentry, so users can log in without
+/// creating an API token first.
+pub struct OAuthApp {
+    client_id: String,
+    client_secret: String,
+    /// must match one of the redirect URIs registered with the application. Sentry falls back to
+    /// the first registered one if it's unset.
+    redirect_uri: Option<String>,
+}
+
+/// Response of the token endpoint, for both the authorization code and the refresh token grant.
+#[derive(Deserialize)]
+pub struct OAuthTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: Option<Timestamp>,
+}
+
+static OAUTH_APP: OnceLock<Option<OAuthApp>> = OnceLock::new();
+
+impl OAuthApp {
+    /// The application configured with `oauth_client_id` and `oauth_client_secret`, or `None` if
+    /// OAuth login is disabled.
+    pub fn get() -> Option<&'static OAuthApp> {
+        OAUTH_APP
+            .get_or_init(|| {
+                let config = Config::get();
+                Some(OAuthApp {
+                    client_id: config.oauth_client_id.clone()?,
+                    client_secret: config.oauth_client_secret.clone()?,
+                    redirect_uri: config.oauth_redirect_uri.clone(),
+                })
+            })
+            .as_ref()
+    }
+
+    /// Where to send the user to authorize sentry.mobi.
+    pub fn authorize_url(&self, upstream: &Upstream, state: &str) -> String {
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", &self.client_id),
+            ("scope", SCOPES),
+            ("state", state),
+        ];
+        if let Some(ref redirect_uri) = self.redirect_uri {
+            params.push(("redirect_uri", redirect_uri));
+        }
+
+        format!(
+            "{}?{}",
+            upstream.web_url("oauth/authorize/"),
+            serde_urlencoded::to_string(params).expect("params are strings")
+        )
+    }
+
+    /// Exchange the code from the callback for tokens.
+    pub async fn exchange_code(
+        &self,
+        upstream: &Upstream,
+        code: &str,
+    ) -> Result<OAuthTokens, Error> {
+        let mut params = vec![("grant_type", "authorization_code"), ("code", code)];
+        if let Some(ref redirect_uri) = self.redirect_uri {
+            params.push(("redirect_uri", redirect_uri));
+        }
+
+        self.request_tokens(upstream, params).await
+    }
+
+    /// Get a new access token before the current one expires.
+    pub async fn refresh(
+        &self,
+        upstream: &Upstream,
+        refresh_token: &str,
+    ) -> Result<OAuthTokens, Error> {
+        self.request_tokens(
+            upstream,
+            vec![
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+            ],
+        )
+        .await
+    }
+
+    async fn request_tokens(
+        &self,
+        upstream: &Upstream,
+        mut params: Vec<(&str, &str)>,
+    ) -> Result<OAuthTokens, Error> {
+        params.push(("client_id", &self.client_id));
+        params.push(("client_secret", &self.client_secret));
+
+        let response = reqwest::Client::new()
+            .post(upstream.web_url("oauth/token/"))
+            .form(&params)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            tracing::debug!("oauth token endpoint returned {}", status);
+            // the code or refresh token was invalid, expired or already used
+            return Err(if status.is_client_error() {
+                Error::Unauthorized { redirect_to: None }
+            } else {
+                Error::Upstream(status)
+            });
+        }
+
+        Ok(response.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use axum::{Form, Json, Router};
+
+    use super::*;
+    use crate::test_util::serve;
+
+    /// Stand-in for Sentry's token endpoint. Accepts the code `good-code` and the refresh token
+    /// `good-refresh`, and fails with a server error for `broken`.
+    async fn token_endpoint(Form(params): Form<HashMap<String, String>>) -> impl IntoResponse {
+        let param = |name: &str| params.get(name).map(String::as_str);
+        if param("client_id") != Some("client") || param("client_secret") != Some("secret") {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+
+        let access_token = match (
+            param("grant_type"),
+            param("code"),
+            param("refresh_token"),
+            param("redirect_uri"),
+        ) {
+            (
+                Some("authorization_code"),
+                Some("good-code"),
+                None,
+                Some("https://mobi/callback"),
+            ) => "access-from-code",
+            (Some("refresh_token"), None, Some("good-refresh"), None) => "access-from-refresh",
+            (_, Some("broken"), _, _) | (_, _, Some("broken"), _) => {
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+            _ => {
+                let error = serde_json::json!({ "error": "invalid_grant" });
+                return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+            }
+        };
+
+        Json(serde_json::json!({
+            "access_token": access_token,
+            "refresh_token": "new-refresh",
+            "expires_at": "2030-01-01T00:00:00Z",
+            "token_type": "bearer",
+            "scope": SCOPES,
+        }))
+        .into_response()
+    }
+
+    async fn serve_token_endpoint() -> Upstream {
+        let router = Router::new().route("/oauth/token/", post(token_endpoint));
+        Upstream::new(&serve(router).await)
+    }
+
+    fn app() -> OAuthApp {
+        OAuthApp {
+            client_id: "client".to_owned(),
+            client_secret: "secret".to_owned(),
+            redirect_uri: Some("https://mobi/callback".to_owned()),
+        }
+    }
+
+    #[tokio::test]
+    async fn exchange_code() {
+        let upstream = serve_token_endpoint().await;
+
+        let tokens = app().exchange_code(&upstream, "good-code").await.unwrap();
+        assert_eq!(tokens.access_token, "access-from-code");
+        assert_eq!(tokens.refresh_token, "new-refresh");
+        assert_eq!(
+            tokens.expires_at,
+            Some("2030-01-01T00:00:00Z".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn exchange_invalid_code() {
+        let upstream = serve_token_endpoint().await;
+
+        let result = app().exchange_code(&upstream, "used-code").await;
+        assert!(matches!(result, Err(Error::Unauthorized { .. })));
+
+        let result = app().exchange_code(&upstream, "broken").await;
+        assert!(matches!(result, Err(Error::Upstream(status)) if status == 500));
+    }
+
+    #[tokio::test]
+    async fn refresh() {
+        let upstream = serve_token_endpoint().await;
+
+        let tokens = app().refresh(&upstream, "good-refresh").await.unwrap();
+        assert_eq!(tokens.access_token, "access-from-refresh");
+        assert_eq!(tokens.refresh_token, "new-refresh");
+
+        let result = app().refresh(&upstream, "revoked-refresh").await;
+        assert!(matches!(result, Err(Error::Unauthorized { .. })));
+    }
+
+    #[test]
+    fn authorize_url() {
+        let url = app().authorize_url(&Upstream::new("https://sentry.example.com"), "abc");
+        assert_eq!(
+            url,
+            "https://sentry.example.com/oauth/authorize/?response_type=code&client_id=client\
+             &scope=org%3Aread+project%3Aread+event%3Aread+event%3Awrite&state=abc\
+             &redirect_uri=https%3A%2F%2Fmobi%2Fcallback"
+        );
+    }
+}
@@ -1,4 +1,6 @@
-use crate::views;
+use std::fmt::Display;
+
+use crate::{metrics, views, Config};
 use axum::Router;
 use axum_extra::routing::{RouterExt, TypedPath};
 use serde::Deserialize;
@@ -15,6 +17,35 @@ pub struct Auth;
 #[typed_path("/auth/logout")]
 pub struct Logout;
 
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/auth/add")]
+pub struct AddAccount;
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/auth/switch")]
+pub struct SwitchAccount;
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/auth/oauth")]
+pub struct OAuthLogin;
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/auth/oauth/callback")]
+pub struct OAuthCallback;
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/admin/audit-log")]
+pub struct AdminAuditLog;
+
+// `/-/` can't be an organization slug, so monitoring can't shadow an organization's page
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/-/healthz")]
+pub struct Healthz;
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/-/metrics")]
+pub struct Metrics;
+
 #[derive(TypedPath, Deserialize)]
 #[typed_path("/:org")]
 pub struct OrganizationDetails {
@@ -36,13 +67,145 @@ pub struct IssueDetails {
     pub issue_id: String,
 }
 
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/:org/:proj/issues/:issue_id/assignee")]
+pub struct IssueAssignee {
+    pub org: String,
+    pub proj: String,
+    pub issue_id: String,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/:org/:proj/issues/:issue_id/priority")]
+pub struct IssuePriority {
+    pub org: String,
+    pub proj: String,
+    pub issue_id: String,
+}
+
+impl From<&IssueAssignee> for IssueDetails {
+    fn from(route: &IssueAssignee) -> Self {
+        IssueDetails {
+            org: route.org.clone(),
+            proj: route.proj.clone(),
+            issue_id: route.issue_id.clone(),
+        }
+    }
+}
+
+impl From<&IssuePriority> for IssueDetails {
+    fn from(route: &IssuePriority) -> Self {
+        IssueDetails {
+            org: route.org.clone(),
+            proj: route.proj.clone(),
+            issue_id: route.issue_id.clone(),
+        }
+    }
+}
+
+/// Absolute path of one of our routes or static files, including the configured base path. All
+/// links and redirects have to go through this.
+pub fn url(path: impl Display) -> String {
+    with_base_path(&Config::get().base_path, &path.to_string())
+}
+
+fn with_base_path(base_path: &str, path: &str) -> String {
+    // nested routers only serve their root without trailing slash
+    if path == "/" && !base_path.is_empty() {
+        base_path.to_owned()
+    } else {
+        format!("{base_path}{path}")
+    }
+}
+
+/// Whether `target` is safe to redirect to after logging in: one of our own pages, given as an
+/// absolute path. Anything else, like `https://evil.example` or `//evil.example`, would let
+/// others send users from our login form to a page of their choosing.
+pub fn is_own_url(target: &str) -> bool {
+    is_own_url_under(&Config::get().base_path, target)
+}
+
+fn is_own_url_under(base_path: &str, target: &str) -> bool {
+    let Some(rest) = target.strip_prefix(&with_base_path(base_path, "/")) else {
+        return false;
+    };
+    let own_path = if base_path.is_empty() {
+        // `url(Index)` is just "/", and a second slash would make this a protocol-relative URL
+        !rest.starts_with('/')
+    } else {
+        rest.is_empty() || rest.starts_with('/') || rest.starts_with('?')
+    };
+
+    // browsers treat backslashes like slashes, and ignore tabs and newlines in URLs
+    own_path && !target.contains(['\\', '\t', '\n', '\r'])
+}
+
 pub fn get_router() -> Router {
     Router::new()
         .typed_get(views::index::index)
         .typed_post(views::auth::auth)
         .typed_post(views::auth::logout)
+        .typed_get(views::auth::add_account)
+        .typed_post(views::auth::switch_account)
+        .typed_get(views::auth::oauth_login)
+        .typed_get(views::auth::oauth_callback)
+        .typed_get(views::audit_log::audit_log)
         .typed_get(views::organization_details::organization_details)
         .typed_get(views::project_details::project_details)
         .typed_get(views::issue_details::issue_details)
         .typed_post(views::issue_details::update_issue_details)
+        .typed_post(views::issue_details::update_assignee)
+        .typed_post(views::issue_details::update_priority)
+}
+
+/// Endpoints for monitoring, see `metrics`.
+pub fn get_monitoring_router() -> Router {
+    Router::new()
+        .typed_get(metrics::healthz)
+        .typed_get(metrics::metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOREIGN: &[&str] = &[
+        "",
+        "acme",
+        "//evil.example",
+        "//evil.example/sentry",
+        "/\\evil.example",
+        "/\\/evil.example",
+        "https://evil.example",
+        "https://evil.example/sentry",
+        "javascript:alert(1)",
+        "/\t/evil.example",
+        "/\n/evil.example",
+        "/sentry/acme\r\n",
+    ];
+
+    #[test]
+    fn own_url_without_base_path() {
+        for target in ["/", "/acme", "/acme/backend/issues/1", "/?redirect_to=%2F"] {
+            assert!(is_own_url_under("", target), "{target:?}");
+        }
+        for target in FOREIGN {
+            assert!(!is_own_url_under("", target), "{target:?}");
+        }
+    }
+
+    #[test]
+    fn own_url_with_base_path() {
+        for target in [
+            "/sentry",
+            "/sentry/",
+            "/sentry/acme",
+            "/sentry?redirect_to=%2F",
+        ] {
+            assert!(is_own_url_under("/sentry", target), "{target:?}");
+        }
+        for target in FOREIGN.iter().chain(&["/", "/acme", "/sentryfoo"]) {
+            assert!(!is_own_url_under("/sentry", target), "{target:?}");
+        }
+    }
 }
@@ -0,0 +1,122 @@
+use std::path::Path;
+use std::sync::OnceLock;
+
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL};
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+use crate::Config;
+
+/// Server-side key to encrypt API tokens with before they are put into the session store, so that
+/// a leaked session store doesn't leak access to Sentry.
+pub struct SecretKey {
+    cipher: XChaCha20Poly1305,
+}
+
+static SECRET_KEY: OnceLock<SecretKey> = OnceLock::new();
+
+impl SecretKey {
+    /// Load the key from `secret_key` (base64-encoded, 32 bytes) or from the file at
+    /// `secret_key_file`, which is created with a new key if it doesn't exist.
+    ///
+    /// Without either, a random key is used, and all sessions become invalid on restart.
+    pub fn init(config: &Config) -> Result<(), String> {
+        let key = if let Some(ref key) = config.secret_key {
+            decode_key(key)?
+        } else if let Some(ref path) = config.secret_key_file {
+            load_or_create_key_file(path)?
+        } else {
+            tracing::warn!(
+                "neither SECRET_KEY nor SECRET_KEY_FILE are set, all sessions will be invalid after restart"
+            );
+            XChaCha20Poly1305::generate_key(&mut OsRng)
+        };
+
+        let secret_key = SecretKey {
+            cipher: XChaCha20Poly1305::new(&key),
+        };
+
+        SECRET_KEY
+            .set(secret_key)
+            .map_err(|_| "secret key is already initialized".to_owned())
+    }
+
+    pub fn get() -> &'static SecretKey {
+        SECRET_KEY.get().expect("secret key is loaded at startup")
+    }
+
+    /// Encrypt a string, returning base64 of nonce and ciphertext.
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("encryption of in-memory data does not fail");
+
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+        BASE64.encode(data)
+    }
+
+    /// Decrypt the output of `encrypt`. Returns `None` if the data was encrypted with a different
+    /// key, or has been tampered with.
+    pub fn decrypt(&self, encrypted: &str) -> Option<String> {
+        let data = BASE64.decode(encrypted).ok()?;
+        if data.len() < 24 {
+            return None;
+        }
+
+        let (nonce, ciphertext) = data.split_at(24);
+        let plaintext = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+}
+
+/// A random, URL-safe string that can't be guessed, e.g. for identifiers in forms or OAuth state.
+pub fn random_id() -> String {
+    let mut bytes = [0u8; 24];
+    OsRng.fill_bytes(&mut bytes);
+    BASE64_URL.encode(bytes)
+}
+
+fn decode_key(encoded: &str) -> Result<Key, String> {
+    let key = BASE64
+        .decode(encoded.trim())
+        .map_err(|e| format!("secret key is not valid base64: {e}"))?;
+
+    if key.len() != 32 {
+        return Err(format!("secret key must be 32 bytes, got {}", key.len()));
+    }
+
+    Ok(*Key::from_slice(&key))
+}
+
+fn load_or_create_key_file(path: &Path) -> Result<Key, String> {
+    match std::fs::read_to_string(path) {
+        Ok(encoded) => decode_key(&encoded),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            tracing::info!("generating new secret key in {}", path.display());
+            let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+            write_key_file(path, &BASE64.encode(key))
+                .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+            Ok(key)
+        }
+        Err(e) => Err(format!("failed to read {}: {e}", path.display())),
+    }
+}
+
+fn write_key_file(path: &Path, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)?.write_all(contents.as_bytes())
+}
@@ -0,0 +1,204 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use reqwest::header::{HeaderMap, HeaderValue};
+use schnellru::{LruMap, Unlimited};
+
+/// How long a cached response is used without asking Sentry whether it's still up to date.
+const FRESH_FOR: Duration = Duration::from_secs(30);
+
+/// How long a cached response is kept around for revalidation with `If-None-Match`.
+const KEEP_FOR: Duration = Duration::from_secs(600);
+
+/// A successful response to a GET request.
+#[derive(Clone)]
+pub(super) struct CachedResponse {
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    fetched_at: Instant,
+}
+
+impl CachedResponse {
+    pub fn new(headers: HeaderMap, body: Bytes) -> Self {
+        CachedResponse {
+            headers,
+            body,
+            fetched_at: Instant::now(),
+        }
+    }
+
+    /// A response that has to be revalidated before it is used again.
+    #[cfg(test)]
+    pub fn stale(headers: HeaderMap, body: Bytes) -> Self {
+        CachedResponse {
+            headers,
+            body,
+            fetched_at: Instant::now() - FRESH_FOR,
+        }
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        self.fetched_at.elapsed() < FRESH_FOR
+    }
+
+    pub fn etag(&self) -> Option<&HeaderValue> {
+        self.headers.get(reqwest::header::ETAG)
+    }
+}
+
+/// Upper bound for the size of all cached bodies. Bodies are what takes up memory, e.g. events
+/// with their stack frames and breadcrumbs.
+const MAX_BYTES: usize = 32 * 1024 * 1024;
+
+/// Bigger bodies are not cached at all, so that a few huge events can't push out everything else.
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+const MAX_ENTRIES: usize = 500;
+
+/// Responses keyed by (token hash, URL). Including the token hash means one user can never see
+/// another user's cached responses.
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+
+struct Cache {
+    /// limited by `insert`, as the limits of `LruMap` can't take the size of bodies into account
+    lru: LruMap<(String, String), CachedResponse, Unlimited>,
+    /// size of all bodies in `lru`
+    bytes: usize,
+}
+
+impl Cache {
+    fn new() -> Self {
+        Cache {
+            lru: LruMap::new(Unlimited),
+            bytes: 0,
+        }
+    }
+
+    fn insert(&mut self, key: (String, String), response: CachedResponse) {
+        self.remove(&key);
+        if response.body.len() > MAX_BODY_BYTES {
+            return;
+        }
+
+        while self.lru.len() >= MAX_ENTRIES || self.bytes + response.body.len() > MAX_BYTES {
+            let Some((_, oldest)) = self.lru.pop_oldest() else {
+                break;
+            };
+            self.bytes -= oldest.body.len();
+        }
+
+        self.bytes += response.body.len();
+        self.lru.insert(key, response);
+    }
+
+    fn remove(&mut self, key: &(String, String)) {
+        if let Some(response) = self.lru.remove(key) {
+            self.bytes -= response.body.len();
+        }
+    }
+}
+
+fn with_cache<R>(f: impl FnOnce(&mut Cache) -> R) -> R {
+    let mut guard = CACHE.lock().unwrap();
+    f(guard.get_or_insert_with(Cache::new))
+}
+
+pub(super) fn get(token_hash: &str, url: &str) -> Option<CachedResponse> {
+    with_cache(|cache| {
+        let key = (token_hash.to_owned(), url.to_owned());
+        let response = cache.lru.get(&key)?;
+
+        if response.fetched_at.elapsed() > KEEP_FOR {
+            cache.remove(&key);
+            return None;
+        }
+
+        Some(response.clone())
+    })
+}
+
+pub(super) fn insert(token_hash: &str, url: &str, response: CachedResponse) {
+    with_cache(|cache| cache.insert((token_hash.to_owned(), url.to_owned()), response));
+}
+
+/// Forget everything cached for a token, e.g. after the user changed something.
+pub(super) fn invalidate(token_hash: &str) {
+    with_cache(|cache| {
+        let keys: Vec<_> = cache
+            .lru
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|(key_token_hash, _)| key_token_hash == token_hash)
+            .cloned()
+            .collect();
+
+        for key in keys {
+            cache.remove(&key);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(len: usize) -> CachedResponse {
+        CachedResponse::new(HeaderMap::new(), Bytes::from(vec![b'x'; len]))
+    }
+
+    fn key(url: &str) -> (String, String) {
+        ("token".to_owned(), url.to_owned())
+    }
+
+    #[test]
+    fn bounded_by_bytes() {
+        let mut cache = Cache::new();
+        let body_len = MAX_BODY_BYTES;
+        for i in 0..(MAX_BYTES / body_len + 10) {
+            cache.insert(key(&i.to_string()), response(body_len));
+            assert!(cache.bytes <= MAX_BYTES);
+        }
+
+        assert_eq!(cache.bytes, MAX_BYTES / body_len * body_len);
+        // the oldest were evicted
+        assert!(cache.lru.get(&key("0")).is_none());
+        let newest = (MAX_BYTES / body_len + 9).to_string();
+        assert!(cache.lru.get(&key(&newest)).is_some());
+    }
+
+    #[test]
+    fn bounded_by_entries() {
+        let mut cache = Cache::new();
+        for i in 0..MAX_ENTRIES + 10 {
+            cache.insert(key(&i.to_string()), response(1));
+        }
+
+        assert_eq!(cache.lru.len(), MAX_ENTRIES);
+        assert_eq!(cache.bytes, MAX_ENTRIES);
+    }
+
+    #[test]
+    fn huge_bodies_are_not_cached() {
+        let mut cache = Cache::new();
+        cache.insert(key("event"), response(10));
+        cache.insert(key("event"), response(MAX_BODY_BYTES + 1));
+
+        // not even the outdated response is kept
+        assert!(cache.lru.get(&key("event")).is_none());
+        assert_eq!(cache.bytes, 0);
+    }
+
+    #[test]
+    fn replacing_keeps_count() {
+        let mut cache = Cache::new();
+        cache.insert(key("a"), response(10));
+        cache.insert(key("a"), response(20));
+        cache.remove(&key("b"));
+
+        assert_eq!(cache.lru.len(), 1);
+        assert_eq!(cache.bytes, 20);
+        cache.remove(&key("a"));
+        assert_eq!(cache.bytes, 0);
+    }
+}
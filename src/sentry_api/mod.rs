@@ -0,0 +1,582 @@
+//! Typed wrapper around the parts of the Sentry API that sentry.mobi uses.
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use reqwest::header::{IF_NONE_MATCH, RETRY_AFTER};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+
+use crate::{metrics, Error, Upstream};
+
+mod cache;
+mod models;
+mod pagination;
+mod rate_limit;
+
+pub use models::*;
+pub use pagination::Page;
+pub use rate_limit::RateLimit;
+
+/// Upper bound for how many pages `SentryApi::get_all` fetches, so that a single view can't cause
+/// an unbounded number of API requests.
+const MAX_PAGES: usize = 20;
+
+/// Sentry API client acting on behalf of one user. Cheap to clone.
+#[derive(Clone)]
+pub struct SentryApi {
+    client: reqwest::Client,
+    /// identifies the user in caches, see `token_hash`
+    token_hash: String,
+    upstream: Upstream,
+    /// where to send the user after logging in again, if Sentry rejects the token
+    redirect_to: Option<String>,
+    /// quota as reported by the most recent response
+    rate_limit: Arc<Mutex<Option<RateLimit>>>,
+}
+
+impl SentryApi {
+    /// `client` is expected to send the token in its default headers.
+    pub fn new(
+        client: reqwest::Client,
+        token: &str,
+        upstream: Upstream,
+        redirect_to: Option<String>,
+    ) -> Self {
+        SentryApi {
+            client,
+            token_hash: token_hash(token),
+            upstream,
+            redirect_to,
+            rate_limit: Default::default(),
+        }
+    }
+
+    pub fn upstream(&self) -> &Upstream {
+        &self.upstream
+    }
+
+    /// The remaining rate-limit quota, if it is running low.
+    pub fn low_rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit.lock().unwrap().filter(RateLimit::is_low)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response, Error> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        let endpoint = endpoint_name(request.url());
+
+        let start = Instant::now();
+        let response = client.execute(request).await;
+        metrics::track_upstream_request(
+            &endpoint,
+            response.as_ref().ok().map(|r| r.status().as_u16()),
+            start,
+        );
+
+        let response = response?;
+        if let Some(rate_limit) = RateLimit::from_headers(response.headers()) {
+            *self.rate_limit.lock().unwrap() = Some(rate_limit);
+        }
+        Ok(response)
+    }
+
+    /// Send an idempotent request, retrying with backoff if we get rate-limited.
+    async fn send_idempotent(&self, request: RequestBuilder) -> Result<reqwest::Response, Error> {
+        let mut attempt = 0;
+
+        loop {
+            let response = self
+                .send(request.try_clone().expect("request body is not a stream"))
+                .await?;
+
+            if response.status() == StatusCode::TOO_MANY_REQUESTS
+                && attempt < rate_limit::MAX_RETRIES
+            {
+                if let Some(delay) = rate_limit::retry_delay(response.headers(), attempt) {
+                    tracing::debug!(
+                        "{} is rate-limited, retrying in {:?}",
+                        response.url(),
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    continue;
+                }
+            }
+
+            return self.check_status(response);
+        }
+    }
+
+    /// Like `reqwest::Response::error_for_status`, but distinguishes the errors that the user can
+    /// do something about.
+    fn check_status(&self, response: reqwest::Response) -> Result<reqwest::Response, Error> {
+        let status = response.status();
+        if !status.is_client_error() && !status.is_server_error() {
+            return Ok(response);
+        }
+
+        tracing::debug!("{} returned {}", response.url(), status);
+
+        Err(match status {
+            StatusCode::UNAUTHORIZED => Error::Unauthorized {
+                redirect_to: self.redirect_to.clone(),
+            },
+            StatusCode::FORBIDDEN => Error::Forbidden,
+            StatusCode::NOT_FOUND => Error::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => Error::RateLimited {
+                retry_after: response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok()?.parse().ok()),
+            },
+            status => Error::Upstream(status),
+        })
+    }
+
+    /// Send a GET request, or answer it from the cache.
+    ///
+    /// Responses are reused for a short time without asking Sentry, unless `revalidate` is set.
+    /// After that, they are revalidated using their `ETag`, if Sentry sent one.
+    async fn get_cached(
+        &self,
+        request: RequestBuilder,
+        revalidate: bool,
+    ) -> Result<cache::CachedResponse, Error> {
+        let url = request
+            .try_clone()
+            .expect("request body is not a stream")
+            .build()?
+            .url()
+            .to_string();
+
+        let cached = cache::get(&self.token_hash, &url);
+        let mut request = request;
+
+        if let Some(ref cached) = cached {
+            if cached.is_fresh() && !revalidate {
+                return Ok(cached.clone());
+            }
+
+            if let Some(etag) = cached.etag() {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+        }
+
+        let response = self.send_idempotent(request).await?;
+
+        let fresh = match cached {
+            Some(cached) if response.status() == StatusCode::NOT_MODIFIED => {
+                cache::CachedResponse::new(cached.headers, cached.body)
+            }
+            _ => {
+                let headers = response.headers().clone();
+                cache::CachedResponse::new(headers, response.bytes().await?)
+            }
+        };
+
+        cache::insert(&self.token_hash, &url, fresh.clone());
+        Ok(fresh)
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        url: String,
+        query: &[(&str, &str)],
+    ) -> Result<T, Error> {
+        let response = self
+            .get_cached(self.client.get(url).query(query), false)
+            .await?;
+        Ok(serde_json::from_slice(&response.body)?)
+    }
+
+    async fn get_page<T: DeserializeOwned>(
+        &self,
+        url: String,
+        query: &[(&str, &str)],
+        cursor: Option<&str>,
+    ) -> Result<Page<T>, Error> {
+        let mut request = self.client.get(url).query(query);
+        if let Some(cursor) = cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+
+        let response = self.get_cached(request, false).await?;
+        let items = serde_json::from_slice(&response.body)?;
+        Ok(Page::from_headers(items, &response.headers))
+    }
+
+    /// Fetch all items of a paginated endpoint by following its cursors.
+    async fn get_all<T: DeserializeOwned>(
+        &self,
+        url: String,
+        query: &[(&str, &str)],
+    ) -> Result<Vec<T>, Error> {
+        let mut items = Vec::new();
+        let mut cursor = None;
+
+        for _ in 0..MAX_PAGES {
+            let page = self.get_page(url.clone(), query, cursor.as_deref()).await?;
+            items.extend(page.items);
+
+            match page.next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => return Ok(items),
+            }
+        }
+
+        tracing::warn!("stopped paginating {} after {} pages", url, MAX_PAGES);
+        Ok(items)
+    }
+
+    /// API URL of an organization-scoped endpoint, on the organization's region. This way
+    /// requests go straight to the region instead of relying on sentry.io to proxy them.
+    async fn org_url(&self, org: &str, path: &str) -> Result<String, Error> {
+        let region_url = match self.upstream.cached_region_url(&self.token_hash, org) {
+            Some(region_url) => region_url,
+            None => {
+                let response: ApiOrganization = self
+                    .get(self.upstream.api_url(&format!("organizations/{org}/")), &[])
+                    .await?;
+                self.upstream
+                    .remember_region_url(&self.token_hash, org, &response.links.region_url)
+            }
+        };
+
+        Ok(format!("{region_url}/api/0/{path}"))
+    }
+
+    /// Who the token belongs to and what it may do. Fails with `Error::Unauthorized` if Sentry
+    /// doesn't accept the token.
+    pub async fn get_index(&self) -> Result<ApiIndex, Error> {
+        let response: ApiIndex = self.get(self.upstream.api_url(""), &[]).await?;
+
+        // the API root is public, so invalid tokens don't get a 401 there
+        if response.auth.is_none() {
+            return Err(Error::Unauthorized {
+                redirect_to: self.redirect_to.clone(),
+            });
+        }
+
+        Ok(response)
+    }
+
+    /// Base URLs of all regions the user has organizations in.
+    ///
+    /// sentry.io splits organizations into regions (`us.sentry.io`, `de.sentry.io`), self-hosted
+    /// instances usually don't. If the instance can't tell us about its regions, assume
+    /// everything lives on the upstream itself.
+    pub async fn list_regions(&self) -> Vec<String> {
+        let response: Result<ApiRegions, _> = self
+            .get(self.upstream.api_url("users/me/regions/"), &[])
+            .await;
+
+        let regions = match response {
+            Ok(response) => response.regions,
+            Err(e) => {
+                tracing::debug!("region discovery failed, falling back to upstream: {}", e);
+                Vec::new()
+            }
+        };
+
+        if regions.is_empty() {
+            return vec![self.upstream.base_url().to_owned()];
+        }
+
+        regions
+            .into_iter()
+            .map(|region| self.upstream.normalize_region_url(&region.url))
+            .collect()
+    }
+
+    pub async fn list_organizations(
+        &self,
+        region_url: &str,
+    ) -> Result<Vec<ApiOrganization>, Error> {
+        let response: Vec<ApiOrganization> = self
+            .get_all(
+                format!("{region_url}/api/0/organizations/"),
+                &[("per_page", "100")],
+            )
+            .await?;
+
+        for org in &response {
+            self.upstream
+                .remember_region_url(&self.token_hash, &org.slug, &org.links.region_url);
+        }
+
+        Ok(response)
+    }
+
+    pub async fn list_projects(&self, org: &str) -> Result<Vec<ApiProject>, Error> {
+        let url = self
+            .org_url(org, &format!("organizations/{org}/projects/"))
+            .await?;
+        self.get_all(url, &[("per_page", "100")]).await
+    }
+
+    pub async fn list_issues(
+        &self,
+        org: &str,
+        proj: &str,
+        query: &str,
+        cursor: Option<&str>,
+    ) -> Result<Page<ApiIssue>, Error> {
+        let url = self
+            .org_url(org, &format!("projects/{org}/{proj}/issues/"))
+            .await?;
+        self.get_page(url, &[("query", query), ("limit", "25")], cursor)
+            .await
+    }
+
+    /// Users that have access to the project, for assigning issues to.
+    pub async fn list_members(&self, org: &str, project_id: &str) -> Result<Vec<ApiUser>, Error> {
+        let url = self
+            .org_url(org, &format!("organizations/{org}/users/"))
+            .await?;
+        let members: Vec<ApiMember> = self.get_all(url, &[("project", project_id)]).await?;
+        Ok(members
+            .into_iter()
+            .filter_map(|member| member.user)
+            .collect())
+    }
+
+    pub async fn list_project_teams(&self, org: &str, proj: &str) -> Result<Vec<ApiTeam>, Error> {
+        let url = self
+            .org_url(org, &format!("projects/{org}/{proj}/teams/"))
+            .await?;
+        self.get_all(url, &[]).await
+    }
+
+    /// The most recent releases of a project, newest first.
+    pub async fn list_recent_releases(
+        &self,
+        org: &str,
+        proj: &str,
+    ) -> Result<Vec<ApiRelease>, Error> {
+        let url = self
+            .org_url(org, &format!("projects/{org}/{proj}/releases/"))
+            .await?;
+        let page = self.get_page(url, &[("per_page", "10")], None).await?;
+        Ok(page.items)
+    }
+
+    pub async fn list_repositories(&self, org: &str) -> Result<Vec<ApiRepository>, Error> {
+        let url = self
+            .org_url(org, &format!("organizations/{org}/repos/"))
+            .await?;
+        self.get_all(url, &[]).await
+    }
+
+    pub async fn get_issue(&self, org: &str, issue_id: &str) -> Result<ApiIssue, Error> {
+        // XXX: the docs here are out of date: https://docs.sentry.io/api/events/retrieve-an-issue/
+        let url = self
+            .org_url(org, &format!("organizations/{org}/issues/{issue_id}/"))
+            .await?;
+        self.get(url, &[]).await
+    }
+
+    /// Like `get_issue`, but asks Sentry even if the issue was fetched moments ago, as it may have
+    /// changed since. Still cheap if it hasn't, thanks to the `ETag`.
+    pub async fn get_issue_revalidated(
+        &self,
+        org: &str,
+        issue_id: &str,
+    ) -> Result<ApiIssue, Error> {
+        let url = self
+            .org_url(org, &format!("organizations/{org}/issues/{issue_id}/"))
+            .await?;
+        let response = self.get_cached(self.client.get(url), true).await?;
+        Ok(serde_json::from_slice(&response.body)?)
+    }
+
+    pub async fn latest_event(&self, org: &str, issue_id: &str) -> Result<ApiEvent, Error> {
+        let url = self
+            .org_url(
+                org,
+                &format!("organizations/{org}/issues/{issue_id}/events/latest/"),
+            )
+            .await?;
+        self.get(url, &[]).await
+    }
+
+    pub async fn update_issue(
+        &self,
+        org: &str,
+        issue_id: &str,
+        update: &ApiUpdate,
+    ) -> Result<(), Error> {
+        // XXX: outdated docs: https://docs.sentry.io/api/events/update-an-issue/
+        let url = self
+            .org_url(org, &format!("organizations/{org}/issues/{issue_id}/"))
+            .await?;
+        let response = self.send(self.client.put(url).json(update)).await?;
+        self.check_status(response)?;
+
+        // the issue shows up in issue streams as well, so there is no point in trying to be
+        // precise here.
+        cache::invalidate(&self.token_hash);
+        Ok(())
+    }
+}
+
+/// The path of an API URL with slugs and IDs replaced, e.g.
+/// `/api/0/organizations/{org}/issues/{issue_id}/`, so that requests can be grouped by endpoint.
+fn endpoint_name(url: &reqwest::Url) -> String {
+    let path = url.path();
+    let path = path.find("/api/0/").map_or(path, |i| &path[i..]);
+
+    let mut segments = Vec::new();
+    let mut placeholders: &[&str] = &[];
+    for segment in path.split('/') {
+        match placeholders.split_first() {
+            Some((placeholder, rest)) if !segment.is_empty() => {
+                segments.push(*placeholder);
+                placeholders = rest;
+            }
+            _ => {
+                placeholders = match segment {
+                    "organizations" => &["{org}"],
+                    "projects" => &["{org}", "{proj}"],
+                    "issues" => &["{issue_id}"],
+                    _ => &[],
+                };
+                segments.push(segment);
+            }
+        }
+    }
+
+    segments.join("/")
+}
+
+/// A hash of an API token, for when we need to tell users apart without holding on to their
+/// token.
+pub fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::http::{header, HeaderMap, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::Router;
+
+    use super::*;
+    use crate::test_util::serve;
+
+    fn endpoint(url: &str) -> String {
+        endpoint_name(&url.parse().unwrap())
+    }
+
+    #[test]
+    fn endpoint_name_replaces_slugs() {
+        assert_eq!(endpoint("https://sentry.io/api/0/"), "/api/0/");
+        assert_eq!(
+            endpoint("https://us.sentry.io/api/0/organizations/"),
+            "/api/0/organizations/"
+        );
+        assert_eq!(
+            endpoint("https://us.sentry.io/api/0/organizations/acme/projects/?per_page=100"),
+            "/api/0/organizations/{org}/projects/"
+        );
+        assert_eq!(
+            endpoint("https://us.sentry.io/api/0/projects/acme/backend/issues/?cursor=0:25:0"),
+            "/api/0/projects/{org}/{proj}/issues/"
+        );
+        assert_eq!(
+            endpoint("https://us.sentry.io/api/0/organizations/acme/issues/123/events/latest/"),
+            "/api/0/organizations/{org}/issues/{issue_id}/events/latest/"
+        );
+    }
+
+    #[test]
+    fn endpoint_name_under_path_prefix() {
+        assert_eq!(
+            endpoint("https://example.com/sentry/api/0/organizations/acme/"),
+            "/api/0/organizations/{org}/"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_cached_revalidates_with_etag() {
+        static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+        static REVALIDATIONS: AtomicUsize = AtomicUsize::new(0);
+
+        async fn handler(headers: HeaderMap) -> impl IntoResponse {
+            REQUESTS.fetch_add(1, Ordering::SeqCst);
+            if headers
+                .get(header::IF_NONE_MATCH)
+                .is_some_and(|v| v == "\"v1\"")
+            {
+                REVALIDATIONS.fetch_add(1, Ordering::SeqCst);
+                return StatusCode::NOT_MODIFIED.into_response();
+            }
+            ([(header::ETAG, "\"v1\"")], r#"{"regions":[]}"#).into_response()
+        }
+
+        let base_url = serve(Router::new().route("/api/0/users/me/regions/", get(handler))).await;
+        let api = SentryApi::new(
+            reqwest::Client::new(),
+            "get_cached_revalidates_with_etag",
+            Upstream::new(&base_url),
+            None,
+        );
+        let url = api.upstream().api_url("users/me/regions/");
+
+        let first = api.get_cached(api.client.get(&url), false).await.unwrap();
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 1);
+
+        // fresh responses are used without asking Sentry
+        let second = api.get_cached(api.client.get(&url), false).await.unwrap();
+        assert_eq!(second.body, first.body);
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 1);
+
+        cache::insert(
+            &api.token_hash,
+            &url,
+            cache::CachedResponse::stale(first.headers.clone(), first.body.clone()),
+        );
+        let third = api.get_cached(api.client.get(&url), false).await.unwrap();
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 2);
+        assert_eq!(REVALIDATIONS.load(Ordering::SeqCst), 1);
+        // the 304 has no body, the cached one is used
+        assert_eq!(third.body, first.body);
+        assert_eq!(third.etag().unwrap(), "\"v1\"");
+        assert!(third.is_fresh());
+
+        // fresh, but the caller wants to know for sure
+        let fourth = api.get_cached(api.client.get(&url), true).await.unwrap();
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 3);
+        assert_eq!(REVALIDATIONS.load(Ordering::SeqCst), 2);
+        assert_eq!(fourth.body, first.body);
+    }
+
+    #[tokio::test]
+    async fn get_cached_is_per_token() {
+        static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+        async fn handler() -> &'static str {
+            REQUESTS.fetch_add(1, Ordering::SeqCst);
+            r#"{"regions":[]}"#
+        }
+
+        let base_url = serve(Router::new().route("/api/0/users/me/regions/", get(handler))).await;
+        for token in ["get_cached_is_per_token_a", "get_cached_is_per_token_b"] {
+            let api = SentryApi::new(
+                reqwest::Client::new(),
+                token,
+                Upstream::new(&base_url),
+                None,
+            );
+            let url = api.upstream().api_url("users/me/regions/");
+            api.get_cached(api.client.get(url), false).await.unwrap();
+        }
+
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 2);
+    }
+}
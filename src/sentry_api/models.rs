@@ -0,0 +1,352 @@
+use std::collections::BTreeMap;
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::upstream::Upstream;
+
+/// Response of the API root, which describes the token that was used to request it.
+#[derive(Deserialize)]
+pub struct ApiIndex {
+    /// missing for tokens that don't belong to a user, e.g. organization tokens
+    #[serde(default)]
+    pub user: Option<ApiUser>,
+    /// null if the token is invalid
+    #[serde(default)]
+    pub auth: Option<ApiAuth>,
+}
+
+#[derive(Deserialize)]
+pub struct ApiAuth {
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiUser {
+    /// missing in sessions from before it was needed
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ApiRegions {
+    #[serde(default)]
+    pub regions: Vec<ApiRegion>,
+}
+
+#[derive(Deserialize)]
+pub struct ApiRegion {
+    pub url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiOrganization {
+    pub name: String,
+    pub slug: String,
+    pub links: ApiOrganizationLinks,
+    #[serde(default)]
+    pub is_bookmarked: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiOrganizationLinks {
+    #[serde(default)]
+    pub region_url: String,
+}
+
+impl ApiOrganization {
+    /// Host of the region the organization lives in, e.g. `us.sentry.io`. On self-hosted
+    /// instances, which have no regions, this is the instance's host.
+    pub fn region_domain(&self, upstream: &Upstream) -> String {
+        let region_url = upstream.normalize_region_url(&self.links.region_url);
+        Upstream::new(&region_url).host().to_owned()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiProject {
+    pub name: String,
+    pub slug: String,
+    #[serde(default)]
+    pub is_bookmarked: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiIssue {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub culprit: String,
+    pub first_seen: Timestamp,
+    pub last_seen: Timestamp,
+    pub status: String,
+    /// e.g. why an issue is archived
+    #[serde(default)]
+    pub substatus: Option<String>,
+    #[serde(default)]
+    pub status_details: ApiStatusDetails,
+    pub level: String,
+    pub permalink: String,
+    pub short_id: String,
+    #[serde(default)]
+    pub logger: Option<String>,
+    pub count: String,
+    pub project: ApiIssueProject,
+    #[serde(default)]
+    pub assigned_to: Option<ApiAssignee>,
+    /// `high`, `medium` or `low`. Missing on instances that predate priorities
+    #[serde(default)]
+    pub priority: Option<String>,
+}
+
+/// How an issue is resolved, or until when it is archived. Archive thresholds are counted from
+/// when it was archived, windows are in minutes.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiStatusDetails {
+    #[serde(default)]
+    pub in_release: Option<String>,
+    #[serde(default)]
+    pub in_next_release: Option<bool>,
+    #[serde(default)]
+    pub in_commit: Option<ApiCommit>,
+    #[serde(default)]
+    pub ignore_until: Option<Timestamp>,
+    #[serde(default)]
+    pub ignore_count: Option<u32>,
+    #[serde(default)]
+    pub ignore_window: Option<u32>,
+    #[serde(default)]
+    pub ignore_user_count: Option<u32>,
+    #[serde(default)]
+    pub ignore_user_window: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct ApiCommit {
+    /// the commit SHA
+    pub id: String,
+    #[serde(default)]
+    pub repository: Option<ApiRepository>,
+}
+
+/// A repository connected to an organization through an integration.
+#[derive(Deserialize)]
+pub struct ApiRepository {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiRelease {
+    pub version: String,
+    /// the version without the package name, for display
+    #[serde(default)]
+    pub short_version: Option<String>,
+}
+
+/// The user or team an issue is assigned to.
+#[derive(Deserialize)]
+pub struct ApiAssignee {
+    /// `user` or `team`
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub id: String,
+    pub name: String,
+}
+
+impl ApiAssignee {
+    /// How Sentry refers to the assignee in updates, see `ApiUpdate::assigned_to`.
+    pub fn actor_id(&self) -> String {
+        format!("{}:{}", self.ty, self.id)
+    }
+
+    pub fn display_name(&self) -> String {
+        if self.ty == "team" {
+            format!("#{}", self.name)
+        } else {
+            self.name.clone()
+        }
+    }
+}
+
+/// A member of an organization, as returned by the users endpoint.
+#[derive(Deserialize)]
+pub struct ApiMember {
+    /// missing for invitations that haven't been accepted yet
+    #[serde(default)]
+    pub user: Option<ApiUser>,
+}
+
+#[derive(Deserialize)]
+pub struct ApiTeam {
+    pub id: String,
+    pub slug: String,
+}
+
+/// The abbreviated project that is embedded in issues.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiIssueProject {
+    pub id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiEvent {
+    #[serde(rename = "dateCreated")]
+    pub timestamp: Timestamp,
+
+    #[serde(default)]
+    pub tags: Vec<ApiTag>,
+
+    pub entries: Vec<ApiEventEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ApiEventEntry {
+    Known(KnownEventEntry),
+    Other {
+        #[serde(rename = "type")]
+        ty: String,
+        #[serde(flatten)]
+        attributes: BTreeMap<String, serde_json::Value>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum KnownEventEntry {
+    Message { data: MessageData },
+    Breadcrumbs { data: BreadcrumbData },
+    Threads { data: ThreadsData },
+    Exception { data: ExceptionData },
+    Request { data: RequestData },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageData {
+    pub formatted: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BreadcrumbData {
+    pub values: Vec<Breadcrumb>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Breadcrumb {
+    pub timestamp: Timestamp,
+    pub level: String,
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExceptionData {
+    pub values: Vec<Exception>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Exception {
+    #[serde(rename = "type", default)]
+    pub ty: String,
+    #[serde(default)]
+    pub value: String,
+    #[serde(default)]
+    pub stacktrace: Option<Stacktrace>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ThreadsData {
+    pub values: Vec<Thread>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Thread {
+    #[serde(default)]
+    pub crashed: bool,
+    #[serde(default)]
+    pub current: bool,
+    #[serde(default)]
+    pub stacktrace: Option<Stacktrace>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Stacktrace {
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Frame {
+    #[serde(default)]
+    pub in_app: bool,
+
+    #[serde(default)]
+    pub filename: Option<String>,
+    #[serde(default)]
+    pub function: Option<String>,
+    #[serde(default)]
+    pub line_no: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiTag {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Deserialize)]
+pub struct RequestData {
+    pub method: String,
+    pub url: String,
+    //#[serde(default)]
+    //query: Vec<String>,
+    //#[serde(default)]
+    //fragment: Option<String>,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub cookies: Vec<(String, String)>,
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
+}
+
+/// Changes to an issue. Fields that are not set are left alone.
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub substatus: Option<String>,
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    pub status_details: serde_json::Map<String, serde_json::Value>,
+    /// `user:<id>` or `team:<id>`, or empty to unassign
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assigned_to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>,
+}
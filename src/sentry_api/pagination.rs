@@ -0,0 +1,142 @@
+use reqwest::header::{HeaderMap, LINK};
+
+/// One page of a cursor-paginated API response.
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// cursor to pass to the same endpoint to get the previous page, if there is one
+    pub prev_cursor: Option<String>,
+    /// cursor to pass to the same endpoint to get the next page, if there is one
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub(super) fn from_headers(items: Vec<T>, headers: &HeaderMap) -> Self {
+        let links = headers
+            .get_all(LINK)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(parse_link_header)
+            .collect::<Vec<_>>();
+
+        let cursor_for = |rel: &str| {
+            links
+                .iter()
+                .find(|link| link.rel == rel && link.results)
+                .map(|link| link.cursor.clone())
+        };
+
+        Page {
+            prev_cursor: cursor_for("previous"),
+            next_cursor: cursor_for("next"),
+            items,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Link {
+    rel: String,
+    results: bool,
+    cursor: String,
+}
+
+/// Parse Sentry's `Link` header, which looks like this:
+///
+/// ```text
+/// <https://sentry.io/api/0/...&cursor=0:0:1>; rel="previous"; results="false"; cursor="0:0:1",
+/// <https://sentry.io/api/0/...&cursor=0:25:0>; rel="next"; results="true"; cursor="0:25:0"
+/// ```
+///
+/// We only care about the attributes and not about the URL, since the URL always points to the
+/// endpoint we just requested.
+fn parse_link_header(value: &str) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    // split on the URL's closing bracket instead of on commas, since commas can appear in
+    // (unencoded) URLs.
+    for (_, attributes) in value
+        .split('<')
+        .skip(1)
+        .filter_map(|link| link.split_once('>'))
+    {
+        let mut link = Link::default();
+
+        for attribute in attributes.split(';') {
+            let Some((key, value)) = attribute.split_once('=') else {
+                continue;
+            };
+
+            let value = value.trim().trim_end_matches(',').trim().trim_matches('"');
+
+            match key.trim() {
+                "rel" => link.rel = value.to_owned(),
+                "results" => link.results = value == "true",
+                "cursor" => link.cursor = value.to_owned(),
+                _ => {}
+            }
+        }
+
+        links.push(link);
+    }
+
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    fn page(link: Option<&str>) -> Page<()> {
+        let mut headers = HeaderMap::new();
+        if let Some(link) = link {
+            headers.insert(LINK, HeaderValue::from_str(link).unwrap());
+        }
+        Page::from_headers(Vec::new(), &headers)
+    }
+
+    #[test]
+    fn first_page() {
+        // the example from the doc comment of `parse_link_header`
+        let page = page(Some(concat!(
+            r#"<https://sentry.io/api/0/...&cursor=0:0:1>; rel="previous"; results="false"; cursor="0:0:1","#,
+            r#" <https://sentry.io/api/0/...&cursor=0:25:0>; rel="next"; results="true"; cursor="0:25:0""#,
+        )));
+        assert_eq!(page.prev_cursor, None);
+        assert_eq!(page.next_cursor.as_deref(), Some("0:25:0"));
+    }
+
+    #[test]
+    fn last_page() {
+        let page = page(Some(
+            "<https://sentry.io/api/0/organizations/?cursor=100:0:1>; rel=\"previous\"; results=\"true\"; cursor=\"100:0:1\", \
+             <https://sentry.io/api/0/organizations/?cursor=100:2:0>; rel=\"next\"; results=\"false\"; cursor=\"100:2:0\"",
+        ));
+        assert_eq!(page.prev_cursor.as_deref(), Some("100:0:1"));
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn commas_in_url() {
+        let links = parse_link_header(
+            "<https://sentry.io/api/0/projects/acme/backend/issues/?query=a,b;c&cursor=0:0:1>; rel=\"previous\"; results=\"false\"; cursor=\"0:0:1\",\
+             <https://sentry.io/api/0/projects/acme/backend/issues/?query=a,b;c&cursor=0:25:0>; rel=\"next\"; results=\"true\"; cursor=\"0:25:0\"",
+        );
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].rel, "previous");
+        assert!(!links[0].results);
+        assert_eq!(links[0].cursor, "0:0:1");
+        assert_eq!(links[1].rel, "next");
+        assert!(links[1].results);
+        assert_eq!(links[1].cursor, "0:25:0");
+    }
+
+    #[test]
+    fn missing_header() {
+        let page = page(None);
+        assert_eq!(page.prev_cursor, None);
+        assert_eq!(page.next_cursor, None);
+        assert!(parse_link_header("").is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+use std::time::Duration;
+
+use jiff::Timestamp;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+
+/// How often an idempotent request is retried after being rate-limited.
+pub(super) const MAX_RETRIES: u32 = 3;
+
+/// If Sentry asks us to wait longer than this, give up and show the user an error instead of
+/// letting the page load forever.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Used when Sentry doesn't tell us how long to wait. Doubled with every attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The rate-limit quota Sentry reported for the last request.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    /// when the quota is refilled
+    pub reset: Option<Timestamp>,
+}
+
+impl RateLimit {
+    pub(super) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        Some(RateLimit {
+            limit: parse_header(headers, "x-sentry-rate-limit-limit")?,
+            remaining: parse_header(headers, "x-sentry-rate-limit-remaining")?,
+            reset: parse_header(headers, "x-sentry-rate-limit-reset")
+                .and_then(|reset| Timestamp::from_second(reset).ok()),
+        })
+    }
+
+    /// Whether less than a fifth of the quota is left.
+    pub fn is_low(&self) -> bool {
+        self.remaining.saturating_mul(5) < self.limit
+    }
+}
+
+/// How long to wait before retrying a rate-limited request, or `None` if it's not worth waiting.
+pub(super) fn retry_delay(headers: &HeaderMap, attempt: u32) -> Option<Duration> {
+    let delay = if let Some(seconds) = parse_header(headers, RETRY_AFTER.as_str()) {
+        Duration::from_secs(seconds)
+    } else if let Some(reset) = RateLimit::from_headers(headers).and_then(|x| x.reset) {
+        let seconds = reset.as_second() - Timestamp::now().as_second();
+        Duration::from_secs(seconds.max(1) as u64)
+    } else {
+        INITIAL_BACKOFF * 2u32.pow(attempt)
+    };
+
+    (delay <= MAX_RETRY_DELAY).then_some(delay)
+}
+
+fn parse_header<T: std::str::FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{HeaderName, HeaderValue};
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_str(value).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn retry_delay_from_retry_after() {
+        let headers = headers(&[("retry-after", "3")]);
+        assert_eq!(retry_delay(&headers, 0), Some(Duration::from_secs(3)));
+        // Sentry knows best, no backoff on top
+        assert_eq!(retry_delay(&headers, 2), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn retry_delay_from_reset() {
+        let reset = (Timestamp::now().as_second() + 5).to_string();
+        let headers = headers(&[
+            ("x-sentry-rate-limit-limit", "40"),
+            ("x-sentry-rate-limit-remaining", "0"),
+            ("x-sentry-rate-limit-reset", &reset),
+        ]);
+        let delay = retry_delay(&headers, 0).unwrap();
+        assert!(delay > Duration::from_secs(3) && delay <= Duration::from_secs(5));
+    }
+
+    #[test]
+    fn retry_delay_reset_in_the_past() {
+        let reset = (Timestamp::now().as_second() - 5).to_string();
+        let headers = headers(&[
+            ("x-sentry-rate-limit-limit", "40"),
+            ("x-sentry-rate-limit-remaining", "0"),
+            ("x-sentry-rate-limit-reset", &reset),
+        ]);
+        assert_eq!(retry_delay(&headers, 0), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn retry_delay_backoff() {
+        let headers = HeaderMap::new();
+        assert_eq!(retry_delay(&headers, 0), Some(INITIAL_BACKOFF));
+        assert_eq!(retry_delay(&headers, 2), Some(INITIAL_BACKOFF * 4));
+        // 500ms * 2^5 = 16s
+        assert_eq!(retry_delay(&headers, 5), None);
+    }
+
+    #[test]
+    fn retry_delay_too_long() {
+        let headers = headers(&[("retry-after", "60")]);
+        assert_eq!(retry_delay(&headers, 0), None);
+    }
+
+    #[test]
+    fn retry_delay_unparseable_retry_after() {
+        // e.g. an HTTP date, which Sentry doesn't send
+        let headers = headers(&[("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT")]);
+        assert_eq!(retry_delay(&headers, 1), Some(INITIAL_BACKOFF * 2));
+    }
+}
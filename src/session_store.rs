@@ -0,0 +1,281 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use time::OffsetDateTime;
+use tokio::io::AsyncWriteExt;
+use tower_sessions::session::{Id, Record};
+use tower_sessions::session_store::{self, ExpiredDeletion};
+use tower_sessions::{MemoryStore, SessionStore};
+
+use crate::metrics;
+use crate::secret_key::random_id;
+
+/// How often expired sessions are deleted from persistent stores.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// The session store, selected at startup.
+#[derive(Debug, Clone)]
+pub enum SessionBackend {
+    /// Sessions are lost on restart.
+    Memory(MemoryStore),
+    File(FileStore),
+}
+
+impl SessionBackend {
+    /// Parse either `memory` or `file:<directory>`.
+    pub async fn from_config(config: &str) -> Result<Self, String> {
+        if config == "memory" {
+            Ok(SessionBackend::Memory(MemoryStore::default()))
+        } else if let Some(dir) = config.strip_prefix("file:") {
+            let store = FileStore::new(dir.into())
+                .await
+                .map_err(|e| format!("failed to create session directory {dir}: {e}"))?;
+            Ok(SessionBackend::File(store))
+        } else {
+            Err(format!(
+                "invalid session store {config:?}, expected 'memory' or 'file:<directory>'"
+            ))
+        }
+    }
+
+    /// Periodically delete expired sessions in the background.
+    pub fn spawn_cleanup(&self) {
+        let SessionBackend::File(store) = self else {
+            // the memory store checks expiry on load, and gets cleaned up on restart anyway
+            return;
+        };
+
+        let store = store.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = store.delete_expired().await {
+                    tracing::error!("failed to delete expired sessions: {}", e);
+                }
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl SessionStore for SessionBackend {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        match self {
+            SessionBackend::Memory(store) => store.create(record).await?,
+            SessionBackend::File(store) => store.create(record).await?,
+        }
+        // the ID may have been changed to avoid a collision
+        metrics::track_session_created(&record.id.to_string(), record.expiry_date);
+        Ok(())
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        match self {
+            SessionBackend::Memory(store) => store.save(record).await?,
+            SessionBackend::File(store) => store.save(record).await?,
+        }
+        metrics::track_session_saved(&record.id.to_string(), record.expiry_date);
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        match self {
+            SessionBackend::Memory(store) => store.load(session_id).await,
+            SessionBackend::File(store) => store.load(session_id).await,
+        }
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        metrics::track_session_deleted(&session_id.to_string());
+        match self {
+            SessionBackend::Memory(store) => store.delete(session_id).await,
+            SessionBackend::File(store) => store.delete(session_id).await,
+        }
+    }
+}
+
+/// Stores each session as a JSON file in a directory, so that logins survive restarts.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub async fn new(dir: PathBuf) -> std::io::Result<Self> {
+        tokio::fs::create_dir_all(&dir).await?;
+        Ok(FileStore { dir })
+    }
+
+    fn path(&self, session_id: &Id) -> PathBuf {
+        // the ID's string representation is URL-safe base64, so it's also safe to use as a
+        // filename
+        self.dir.join(format!("{session_id}.json"))
+    }
+
+    async fn write(&self, record: &Record, create_new: bool) -> std::io::Result<()> {
+        let data = serde_json::to_vec(record)?;
+
+        // when overwriting, write and rename so that a crash never leaves a half-written session
+        // behind. concurrent requests on the same session can both save it, so every write gets
+        // its own temporary file.
+        let path = if create_new {
+            self.path(&record.id)
+        } else {
+            self.dir.join(format!("{}.{}.tmp", record.id, random_id()))
+        };
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // sessions contain API tokens
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(&path).await?;
+        file.write_all(&data).await?;
+        file.sync_all().await?;
+
+        if !create_new {
+            tokio::fs::rename(&path, self.path(&record.id)).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn read(&self, path: &Path) -> session_store::Result<Option<Record>> {
+        let data = match tokio::fs::read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(backend_error(e)),
+        };
+
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| session_store::Error::Decode(e.to_string()))
+    }
+
+    async fn remove(&self, path: &Path) -> session_store::Result<()> {
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(backend_error(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn backend_error(e: std::io::Error) -> session_store::Error {
+    session_store::Error::Backend(e.to_string())
+}
+
+#[async_trait]
+impl SessionStore for FileStore {
+    async fn create(&self, record: &mut Record) -> session_store::Result<()> {
+        loop {
+            match self.write(record, true).await {
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => record.id = Id::default(),
+                result => return result.map_err(backend_error),
+            }
+        }
+    }
+
+    async fn save(&self, record: &Record) -> session_store::Result<()> {
+        self.write(record, false).await.map_err(backend_error)
+    }
+
+    async fn load(&self, session_id: &Id) -> session_store::Result<Option<Record>> {
+        let path = self.path(session_id);
+        let record = self.read(&path).await?;
+
+        match record {
+            Some(record) if record.expiry_date <= OffsetDateTime::now_utc() => {
+                self.remove(&path).await?;
+                Ok(None)
+            }
+            record => Ok(record),
+        }
+    }
+
+    async fn delete(&self, session_id: &Id) -> session_store::Result<()> {
+        self.remove(&self.path(session_id)).await
+    }
+}
+
+#[async_trait]
+impl ExpiredDeletion for FileStore {
+    async fn delete_expired(&self) -> session_store::Result<()> {
+        let mut entries = tokio::fs::read_dir(&self.dir)
+            .await
+            .map_err(backend_error)?;
+        let now = OffsetDateTime::now_utc();
+
+        while let Some(entry) = entries.next_entry().await.map_err(backend_error)? {
+            let path = entry.path();
+            if path.extension() == Some("tmp".as_ref()) {
+                // left behind by a crash while saving. anything this old is not being written
+                // anymore.
+                let modified = entry.metadata().await.and_then(|m| m.modified());
+                if modified
+                    .is_ok_and(|modified| modified.elapsed().unwrap_or_default() > CLEANUP_INTERVAL)
+                {
+                    self.remove(&path).await?;
+                }
+                continue;
+            }
+            if path.extension() != Some("json".as_ref()) {
+                continue;
+            }
+
+            match self.read(&path).await {
+                Ok(Some(record)) if record.expiry_date > now => {
+                    // e.g. after a restart, when the metrics don't know about it yet
+                    metrics::track_session_saved(&record.id.to_string(), record.expiry_date);
+                }
+                Ok(_) => self.remove(&path).await?,
+                Err(e) => {
+                    tracing::warn!("deleting unreadable session {}: {}", path.display(), e);
+                    self.remove(&path).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_saves() {
+        let dir = std::env::temp_dir().join(format!("sentry-mobi-sessions-{}", random_id()));
+        let store = FileStore::new(dir.clone()).await.unwrap();
+
+        let mut record = Record {
+            id: Id::default(),
+            data: Default::default(),
+            expiry_date: OffsetDateTime::now_utc() + Duration::hours(1),
+        };
+        store.create(&mut record).await.unwrap();
+
+        let saves = (0..20).map(|i| {
+            let store = store.clone();
+            let mut record = record.clone();
+            record.data.insert("i".to_owned(), i.into());
+            async move { store.save(&record).await }
+        });
+        for result in futures::future::join_all(saves).await {
+            result.unwrap();
+        }
+
+        let loaded = store.load(&record.id).await.unwrap().unwrap();
+        assert!(loaded.data["i"].is_u64());
+
+        let leftovers = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(leftovers, 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,11 @@
+//! Helpers shared by the tests of several modules.
+
+use axum::Router;
+
+/// Serve `router` on a random local port and return its base URL, without trailing slash.
+pub async fn serve(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("http://{addr}")
+}
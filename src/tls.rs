@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use axum_server::tls_rustls::RustlsConfig;
+
+use crate::config::Tls;
+
+/// Load or generate the certificate to serve HTTPS with.
+pub async fn load(tls: &Tls) -> Result<RustlsConfig, String> {
+    // ring is the only provider compiled in, but rustls still wants to be told
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let (cert, key) = match tls {
+        Tls::Files { cert, key } => (read(cert)?, read(key)?),
+        Tls::SelfSigned { files: None } => {
+            tracing::warn!(
+                "using a temporary self-signed certificate, browsers will warn about it"
+            );
+            generate_self_signed()?
+        }
+        Tls::SelfSigned {
+            files: Some((cert, key)),
+        } => {
+            if cert.exists() {
+                (read(cert)?, read(key)?)
+            } else {
+                tracing::info!("generating self-signed certificate in {}", cert.display());
+                let (cert_pem, key_pem) = generate_self_signed()?;
+                write(cert, &cert_pem, 0o644)?;
+                write(key, &key_pem, 0o600)?;
+                (cert_pem, key_pem)
+            }
+        }
+    };
+
+    RustlsConfig::from_pem(cert, key)
+        .await
+        .map_err(|e| format!("invalid TLS certificate or key: {e}"))
+}
+
+/// A certificate for `localhost` and the machine's hostname, so that it works on a LAN.
+fn generate_self_signed() -> Result<(Vec<u8>, Vec<u8>), String> {
+    let mut names = vec!["localhost".to_owned()];
+    if let Ok(hostname) = std::fs::read_to_string("/etc/hostname") {
+        let hostname = hostname.trim();
+        if !hostname.is_empty() && hostname != "localhost" {
+            names.push(hostname.to_owned());
+            names.push(format!("{hostname}.local"));
+        }
+    }
+
+    let certified = rcgen::generate_simple_self_signed(names)
+        .map_err(|e| format!("failed to generate self-signed certificate: {e}"))?;
+    Ok((
+        certified.cert.pem().into_bytes(),
+        certified.key_pair.serialize_pem().into_bytes(),
+    ))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))
+}
+
+fn write(path: &Path, contents: &[u8], mode: u32) -> Result<(), String> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|e| format!("failed to write {}: {e}", path.display()))
+}
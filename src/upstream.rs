@@ -0,0 +1,106 @@
+use std::sync::{Mutex, OnceLock};
+
+use schnellru::{ByLength, LruMap};
+
+use crate::Config;
+
+pub const DEFAULT_UPSTREAM: &str = "https://sentry.io";
+
+/// A Sentry instance that API calls and "open in sentry" links go to. Either sentry.io or a
+/// self-hosted instance.
+#[derive(Clone, Debug)]
+pub struct Upstream {
+    /// scheme and host, without trailing slash. e.g. `https://sentry.io`
+    base_url: String,
+}
+
+static UPSTREAMS: OnceLock<Vec<Upstream>> = OnceLock::new();
+
+/// Region base URL per (token, organization slug). Organizations don't move between regions, so
+/// this is never invalidated.
+static REGIONS: Mutex<Option<LruMap<(String, String), String>>> = Mutex::new(None);
+
+impl Upstream {
+    pub fn new(base_url: &str) -> Self {
+        Upstream {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// The instances users can log in to, configured using `sentry_url`. It may contain several
+    /// URLs separated by spaces.
+    pub fn all() -> &'static [Upstream] {
+        UPSTREAMS.get_or_init(|| {
+            let upstreams: Vec<_> = Config::get()
+                .sentry_url
+                .split_whitespace()
+                .map(Upstream::new)
+                .collect();
+            if upstreams.is_empty() {
+                vec![Upstream::new(DEFAULT_UPSTREAM)]
+            } else {
+                upstreams
+            }
+        })
+    }
+
+    /// The first configured instance, used if the user doesn't choose one.
+    pub fn get() -> &'static Upstream {
+        &Upstream::all()[0]
+    }
+
+    /// A configured instance by its base URL. Users can't make us talk to anything else.
+    pub fn find(base_url: &str) -> Option<&'static Upstream> {
+        let base_url = base_url.trim_end_matches('/');
+        Upstream::all().iter().find(|u| u.base_url == base_url)
+    }
+
+    /// The host, for telling instances apart in the UI.
+    pub fn host(&self) -> &str {
+        self.base_url
+            .split_once("://")
+            .map_or(&*self.base_url, |(_, host)| host)
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// URL to an API endpoint, `path` being relative to `/api/0/`
+    pub fn api_url(&self, path: &str) -> String {
+        format!("{}/api/0/{path}", self.base_url)
+    }
+
+    /// URL to a page in the real Sentry UI
+    pub fn web_url(&self, path: &str) -> String {
+        format!("{}/{path}", self.base_url)
+    }
+
+    /// Base URL of the region an organization lives in, if we have seen the organization
+    /// before.
+    pub fn cached_region_url(&self, token: &str, org: &str) -> Option<String> {
+        with_regions(|lru| lru.get(&(token.to_owned(), org.to_owned())).cloned())
+    }
+
+    /// Record an organization's region from its `links.regionUrl`, and return the normalized
+    /// region URL.
+    pub fn remember_region_url(&self, token: &str, org: &str, region_url: &str) -> String {
+        let region_url = self.normalize_region_url(region_url);
+        with_regions(|lru| lru.insert((token.to_owned(), org.to_owned()), region_url.clone()));
+        region_url
+    }
+
+    /// self-hosted instances don't have regions and send an empty `regionUrl`
+    pub fn normalize_region_url(&self, region_url: &str) -> String {
+        if region_url.is_empty() {
+            self.base_url.clone()
+        } else {
+            region_url.trim_end_matches('/').to_owned()
+        }
+    }
+}
+
+fn with_regions<R>(f: impl FnOnce(&mut LruMap<(String, String), String>) -> R) -> R {
+    let mut guard = REGIONS.lock().unwrap();
+    f(guard.get_or_insert_with(|| LruMap::new(ByLength::new(1000))))
+}
@@ -0,0 +1,79 @@
+use axum::response::IntoResponse;
+
+use crate::routes::{url, IssueDetails};
+use crate::views::helpers::{html, print_relative_time, wrap_admin_template, Html, LayoutOptions};
+use crate::{AuditLog, Error, SentryToken};
+
+/// Read-only view of the audit log, for the users listed in `audit_log_viewers`.
+pub async fn audit_log(
+    _: crate::routes::AdminAuditLog,
+    token: SentryToken,
+) -> Result<impl IntoResponse, Error> {
+    let audit_log = AuditLog::get().ok_or(Error::NotFound)?;
+    if !token.is_logged_in() {
+        return Err(token.needs_auth());
+    }
+
+    if !token.may_view_audit_log() {
+        return Err(Error::AccessDenied);
+    }
+
+    let entries = audit_log.recent_entries().await?;
+
+    let body = wrap_admin_template(
+        LayoutOptions {
+            title: "audit log".to_owned(),
+            accounts: token.accounts(),
+            ..Default::default()
+        },
+        html! {
+            h2 {
+                "audit log "
+                small.secondary { "(" (entries.len()) " most recent)" }
+            }
+
+            div.overflow-auto {
+                table.striped {
+                    thead {
+                        tr {
+                            th { "when" }
+                            th { "who" }
+                            th { "issue" }
+                            th { "change" }
+                            th { "from" }
+                        }
+                    }
+                    tbody {
+                        @for entry in entries {
+                            tr {
+                                td { (print_relative_time(entry.timestamp)) " ago" }
+                                td title=(entry.upstream) {
+                                    (entry.user.as_deref().unwrap_or("organization token"))
+                                }
+                                td {
+                                    a href=(url(IssueDetails {
+                                        org: entry.org.clone(),
+                                        proj: entry.project.clone(),
+                                        issue_id: entry.issue_id.clone(),
+                                    })) {
+                                        (entry.org) "/" (entry.project) "/" (entry.issue_id)
+                                    }
+                                }
+                                td data-tooltip=(entry.update) {
+                                    (entry.field) ": " (entry.old_value) " → " (entry.new_value)
+                                }
+                                td {
+                                    @if let Some(client_ip) = entry.client_ip {
+                                        code { (client_ip) }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        },
+    );
+
+    Ok(Html(body))
+}
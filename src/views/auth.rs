@@ -1,19 +1,29 @@
 use std::sync::Mutex;
 
 use async_trait::async_trait;
-use axum::extract::FromRequestParts;
+use axum::extract::{FromRequestParts, OriginalUri, Query, RawPathParams};
 use axum::http::{request::Parts, StatusCode, Uri};
-use axum::response::{IntoResponse, Redirect};
+use axum::response::{IntoResponse, Redirect, Response};
 use axum::Form;
+use jiff::{SignedDuration, Timestamp};
 use schnellru::{ByLength, LruMap};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tower_sessions::Session;
 
-use crate::Error;
+use crate::metrics;
+use crate::routes::{is_own_url, url, Index};
+use crate::secret_key::random_id;
+use crate::sentry_api::{token_hash, ApiUser};
+use crate::views::helpers::{login_form, wrap_template, Html, LayoutOptions};
+use crate::views::index::RedirectTo;
+use crate::{AuditLog, Error, OAuthApp, SecretKey, SentryApi, Upstream};
 
 #[derive(Deserialize)]
 pub struct AuthParams {
     token: String,
+    /// base URL of one of the configured instances, see `Upstream::all`
+    #[serde(default)]
+    upstream: Option<String>,
     #[serde(default)]
     redirect_to: Option<String>,
 }
@@ -22,64 +32,536 @@ pub async fn auth(
     _: crate::routes::Auth,
     session: Session,
     Form(params): Form<AuthParams>,
+) -> Result<Response, Error> {
+    let token = params.token.trim();
+    let redirect_to = params.redirect_to.as_deref();
+
+    let upstream = match params.upstream {
+        Some(ref base_url) => match Upstream::find(base_url) {
+            Some(upstream) => upstream,
+            None => return Ok(login_error(redirect_to, "unknown sentry instance")),
+        },
+        None => Upstream::get(),
+    };
+
+    // anything else can't be sent in a header anyway
+    if token.is_empty() || !token.chars().all(|c| c.is_ascii_graphic()) {
+        return Ok(login_error(
+            redirect_to,
+            "this doesn't look like an API token",
+        ));
+    }
+
+    match log_in(&session, upstream, token, None).await {
+        Ok(()) => Ok(redirect_after_login(redirect_to)),
+        Err(Error::Unauthorized { .. }) => {
+            Ok(login_error(redirect_to, "sentry did not accept this token"))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Start logging in through Sentry's OAuth authorization code flow.
+pub async fn oauth_login(
+    _: crate::routes::OAuthLogin,
+    session: Session,
+    Query(params): Query<RedirectTo>,
 ) -> Result<impl IntoResponse, Error> {
-    session.insert(SESSION_COOKIE_KEY, params.token).await?;
-    Ok(Redirect::to(params.redirect_to.as_deref().unwrap_or("/")))
+    let app = OAuthApp::get().ok_or(Error::NotFound)?;
+
+    let pending = PendingOAuthLogin {
+        state: random_id(),
+        redirect_to: params.redirect_to.filter(|target| is_own_url(target)),
+    };
+    let url = app.authorize_url(Upstream::get(), &pending.state);
+    session.insert(SESSION_OAUTH_STATE_KEY, pending).await?;
+    Ok(Redirect::to(&url))
+}
+
+#[derive(Deserialize)]
+pub struct OAuthCallbackParams {
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    state: Option<String>,
+    /// set instead of `code` if the user denied access
+    #[serde(default)]
+    error: Option<String>,
+}
+
+pub async fn oauth_callback(
+    _: crate::routes::OAuthCallback,
+    session: Session,
+    Query(params): Query<OAuthCallbackParams>,
+) -> Result<Response, Error> {
+    let app = OAuthApp::get().ok_or(Error::NotFound)?;
+    let pending = take_pending_login(&session, params.state.as_deref()).await?;
+
+    let Some(pending) = pending else {
+        return Ok(login_error(None, "the login has expired, please try again"));
+    };
+    let redirect_to = pending.redirect_to.as_deref();
+
+    let code = match (params.code, params.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            let error = error.unwrap_or_else(|| "no code".to_owned());
+            return Ok(login_error(
+                redirect_to,
+                &format!("sentry did not authorize the login: {error}"),
+            ));
+        }
+    };
+
+    let tokens = match app.exchange_code(Upstream::get(), &code).await {
+        Ok(tokens) => tokens,
+        Err(Error::Unauthorized { .. }) => {
+            return Ok(login_error(
+                redirect_to,
+                "the login has expired, please try again",
+            ));
+        }
+        Err(e) => return Err(e),
+    };
+
+    let grant = OAuthGrant {
+        encrypted_refresh_token: SecretKey::get().encrypt(&tokens.refresh_token),
+        expires_at: tokens.expires_at,
+    };
+    log_in(&session, Upstream::get(), &tokens.access_token, Some(grant)).await?;
+    Ok(redirect_after_login(redirect_to))
+}
+
+/// The login started by `oauth_login`, if `state` belongs to it. It is removed from the session
+/// either way, so that the callback can't be replayed.
+async fn take_pending_login(
+    session: &Session,
+    state: Option<&str>,
+) -> Result<Option<PendingOAuthLogin>, Error> {
+    let pending: Option<PendingOAuthLogin> = session.remove(SESSION_OAUTH_STATE_KEY).await?;
+    Ok(pending.filter(|pending| state == Some(pending.state.as_str())))
+}
+
+/// Check the token with Sentry, then add it to the session's accounts and make it the active one.
+/// `grant` is set if the token came from OAuth and can be refreshed.
+async fn log_in(
+    session: &Session,
+    upstream: &Upstream,
+    token: &str,
+    grant: Option<OAuthGrant>,
+) -> Result<(), Error> {
+    let api = SentryApi::new(client(token), token, upstream.clone(), None);
+    let index = api.get_index().await?;
+
+    let account = Account {
+        id: random_id(),
+        upstream: upstream.base_url().to_owned(),
+        encrypted_token: SecretKey::get().encrypt(token),
+        identity: Identity {
+            user: index.user,
+            scopes: index.auth.map(|auth| auth.scopes).unwrap_or_default(),
+        },
+        grant,
+        organizations: Vec::new(),
+    };
+
+    let mut accounts = Accounts::load(session).await;
+    accounts.add(account);
+    accounts.save(session).await
+}
+
+fn redirect_after_login(redirect_to: Option<&str>) -> Response {
+    match redirect_to.filter(|target| is_own_url(target)) {
+        Some(redirect_to) => Redirect::to(redirect_to).into_response(),
+        None => Redirect::to(&url(Index)).into_response(),
+    }
+}
+
+fn login_error(redirect_to: Option<&str>, error: &str) -> Response {
+    let body = wrap_template(
+        LayoutOptions::default(),
+        login_form(redirect_to, Some(error)),
+    );
+    (StatusCode::UNPROCESSABLE_ENTITY, Html(body)).into_response()
+}
+
+/// The login form, for adding another account to the session.
+pub async fn add_account(
+    _: crate::routes::AddAccount,
+    Query(params): Query<RedirectTo>,
+) -> impl IntoResponse {
+    Html(wrap_template(
+        LayoutOptions {
+            title: "add account".to_owned(),
+            ..Default::default()
+        },
+        login_form(params.redirect_to.as_deref(), None),
+    ))
 }
 
+#[derive(Deserialize)]
+pub struct AccountParams {
+    account: String,
+}
+
+pub async fn switch_account(
+    _: crate::routes::SwitchAccount,
+    session: Session,
+    Form(params): Form<AccountParams>,
+) -> Result<impl IntoResponse, Error> {
+    let mut accounts = Accounts::load(&session).await;
+    if accounts.get(&params.account).is_none() {
+        return Err(Error::NotFound);
+    }
+
+    accounts.active = Some(params.account);
+    accounts.save(&session).await?;
+    Ok(Redirect::to(&url(Index)))
+}
+
+/// Log out of one account, and switch to the next one if there is any.
 pub async fn logout(
     _: crate::routes::Logout,
     session: Session,
+    Form(params): Form<AccountParams>,
 ) -> Result<impl IntoResponse, Error> {
-    session.remove::<String>(SESSION_COOKIE_KEY).await?;
+    let mut accounts = Accounts::load(&session).await;
+    accounts.remove(&params.account);
+    accounts.save(&session).await?;
     // cycle ID to invalidate browser caches, which have 'Vary: Cookie'
     session.cycle_id().await?;
-    Ok(Redirect::to("/"))
+    Ok(Redirect::to(&url(Index)))
+}
+
+const SESSION_ACCOUNTS_KEY: &str = "sentry_accounts";
+const SESSION_OAUTH_STATE_KEY: &str = "sentry_oauth_state";
+
+/// How long before expiry an OAuth access token is refreshed.
+const REFRESH_BEFORE: SignedDuration = SignedDuration::from_secs(300);
+
+/// Stored between redirecting to Sentry and the OAuth callback.
+#[derive(Serialize, Deserialize)]
+struct PendingOAuthLogin {
+    state: String,
+    redirect_to: Option<String>,
+}
+
+/// Lets us get a new access token when the current one expires, if the user logged in through
+/// OAuth.
+#[derive(Clone, Serialize, Deserialize)]
+struct OAuthGrant {
+    /// encrypted with the `SecretKey`, like the access token
+    encrypted_refresh_token: String,
+    expires_at: Option<Timestamp>,
 }
 
-const SESSION_COOKIE_KEY: &str = "sentry_token";
+impl OAuthGrant {
+    fn needs_refresh(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at.duration_since(Timestamp::now()) < REFRESH_BEFORE)
+    }
+}
+
+/// Who a token belongs to, as reported by Sentry at login.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Identity {
+    /// `None` for tokens that don't belong to a user
+    pub user: Option<ApiUser>,
+    pub scopes: Vec<String>,
+}
+
+/// One login to a Sentry instance. A session can have several, e.g. a personal and an on-call
+/// account, or accounts on different instances.
+#[derive(Clone, Serialize, Deserialize)]
+struct Account {
+    /// random, identifies the account in forms
+    id: String,
+    /// base URL of one of the configured `Upstream`s
+    upstream: String,
+    /// encrypted with the `SecretKey`
+    encrypted_token: String,
+    identity: Identity,
+    grant: Option<OAuthGrant>,
+    /// slugs of the organizations the account has access to, as of the last time the user looked
+    /// at the organization list. Used to pick the account for an organization's pages.
+    organizations: Vec<String>,
+}
+
+impl Account {
+    /// Who the account is, for telling whether logging in again is the same account. `None` for
+    /// tokens that don't belong to a user, e.g. organization tokens, which are never the same
+    /// account as each other.
+    fn user_key(&self) -> Option<(&str, &str)> {
+        let user = self
+            .identity
+            .user
+            .as_ref()
+            .filter(|user| !user.id.is_empty())?;
+        Some((&self.upstream, &user.id))
+    }
+
+    fn may_view_audit_log(&self) -> bool {
+        Upstream::find(&self.upstream)
+            .is_some_and(|upstream| AuditLog::may_view(upstream, &self.identity))
+    }
+
+    fn name(&self) -> String {
+        let user = match self.identity.user {
+            Some(ref user) if !user.name.is_empty() => &user.name,
+            Some(ref user) => &user.email,
+            None => "organization token",
+        };
+
+        match Upstream::find(&self.upstream) {
+            Some(upstream) if Upstream::all().len() > 1 => {
+                format!("{user} on {}", upstream.host())
+            }
+            _ => user.to_owned(),
+        }
+    }
+
+    /// Refresh the access token if it's about to expire. Failures are only logged, as the current
+    /// token may still work for a few minutes, and Sentry will tell the user to log in again once
+    /// it doesn't.
+    async fn refresh(&mut self) -> bool {
+        let Some(ref grant) = self.grant else {
+            return false;
+        };
+        if !grant.needs_refresh() {
+            return false;
+        }
+
+        let (Some(app), Some(upstream)) = (OAuthApp::get(), Upstream::find(&self.upstream)) else {
+            return false;
+        };
+        let Some(refresh_token) = SecretKey::get().decrypt(&grant.encrypted_refresh_token) else {
+            return false;
+        };
+
+        match app.refresh(upstream, &refresh_token).await {
+            Ok(tokens) => {
+                self.encrypted_token = SecretKey::get().encrypt(&tokens.access_token);
+                self.grant = Some(OAuthGrant {
+                    encrypted_refresh_token: SecretKey::get().encrypt(&tokens.refresh_token),
+                    expires_at: tokens.expires_at,
+                });
+                true
+            }
+            Err(e) => {
+                tracing::warn!("failed to refresh oauth token: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// All accounts of a session.
+#[derive(Default, Serialize, Deserialize)]
+struct Accounts {
+    accounts: Vec<Account>,
+    /// ID of the account used for pages that aren't about a specific organization
+    active: Option<String>,
+}
+
+impl Accounts {
+    async fn load(session: &Session) -> Self {
+        session
+            .get(SESSION_ACCOUNTS_KEY)
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    async fn save(&self, session: &Session) -> Result<(), Error> {
+        session.insert(SESSION_ACCOUNTS_KEY, self).await?;
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> Option<&Account> {
+        self.accounts.iter().find(|account| account.id == id)
+    }
+
+    fn get_mut(&mut self, id: &str) -> Option<&mut Account> {
+        self.accounts.iter_mut().find(|account| account.id == id)
+    }
 
-/// A sentry API token. may be empty, in which case self.client will redirect to login
-#[derive(Default)]
+    /// Add an account and make it the active one. Logging in to the same account again replaces
+    /// it.
+    fn add(&mut self, account: Account) {
+        if let Some(key) = account.user_key() {
+            self.accounts
+                .retain(|existing| existing.user_key() != Some(key));
+        }
+        self.active = Some(account.id.clone());
+        self.accounts.push(account);
+    }
+
+    fn remove(&mut self, id: &str) {
+        self.accounts.retain(|account| account.id != id);
+        if self.active.as_deref() == Some(id) {
+            self.active = self.accounts.first().map(|account| account.id.clone());
+        }
+    }
+
+    fn active(&self) -> Option<&Account> {
+        self.active
+            .as_deref()
+            .and_then(|id| self.get(id))
+            .or(self.accounts.first())
+    }
+
+    /// The account to use for an organization's pages: the active account if it has access to
+    /// the organization, otherwise any other account that has.
+    fn for_organization(&self, org: &str) -> Option<&Account> {
+        let has_org = |account: &&Account| account.organizations.iter().any(|o| o == org);
+        self.active()
+            .filter(has_org)
+            .or_else(|| self.accounts.iter().find(has_org))
+            .or(self.active())
+    }
+}
+
+/// What the header shows about an account.
+pub struct AccountSummary {
+    pub id: String,
+    pub name: String,
+    pub identity: Identity,
+    /// whether the account is used for the current page
+    pub current: bool,
+    pub may_view_audit_log: bool,
+}
+
+/// The accounts of the session, and which one to use for the current request. If there are none,
+/// self.api will redirect to login.
 pub struct SentryToken {
-    pub(super) token: String,
+    session: Session,
+    accounts: Accounts,
+    /// ID of the account picked for this request
+    current: Option<String>,
     redirect_to: Uri,
 }
 
 impl SentryToken {
-    pub(super) fn client(&self) -> Result<reqwest::Client, Error> {
-        if self.token.is_empty() {
-            return Err(Error::NeedsAuth {
-                redirect_to: Some(self.redirect_to.to_string()),
-            });
-        }
+    fn account(&self) -> Option<&Account> {
+        self.accounts.get(self.current.as_deref()?)
+    }
 
-        static CLIENT: Mutex<Option<LruMap<String, reqwest::Client>>> = Mutex::new(None);
+    /// Sessions from before a key change can't be decrypted anymore, those count as logged out.
+    fn decrypt(&self) -> Option<String> {
+        SecretKey::get().decrypt(&self.account()?.encrypted_token)
+    }
 
-        let mut guard = CLIENT.lock().unwrap();
-        let lru = guard.get_or_insert_with(|| LruMap::new(ByLength::new(50)));
+    pub(super) fn is_logged_in(&self) -> bool {
+        self.decrypt().is_some()
+    }
 
-        if let Some(client) = lru.get(&self.token) {
-            return Ok(client.clone());
+    /// The Sentry user of the current account, `None` for organization tokens.
+    pub(super) fn current_user(&self) -> Option<ApiUser> {
+        self.account()?.identity.user.clone()
+    }
+
+    /// Whether the current account may browse the audit log. Other accounts of the session don't
+    /// count, they may be on an instance that is less trustworthy.
+    pub(super) fn may_view_audit_log(&self) -> bool {
+        self.account().is_some_and(Account::may_view_audit_log)
+    }
+
+    /// All accounts of the session, for the account switcher.
+    pub(super) fn accounts(&self) -> Vec<AccountSummary> {
+        self.accounts
+            .accounts
+            .iter()
+            .map(|account| AccountSummary {
+                id: account.id.clone(),
+                name: account.name(),
+                identity: account.identity.clone(),
+                current: self.current.as_ref() == Some(&account.id),
+                may_view_audit_log: account.may_view_audit_log(),
+            })
+            .collect()
+    }
+
+    /// Remember which organizations the current account has access to, so that their pages keep
+    /// using this account after switching to another one.
+    pub(super) async fn remember_organizations(
+        &mut self,
+        organizations: Vec<String>,
+    ) -> Result<(), Error> {
+        let Some(account) = self
+            .current
+            .as_deref()
+            .and_then(|id| self.accounts.get_mut(id))
+        else {
+            return Ok(());
+        };
+
+        if account.organizations != organizations {
+            account.organizations = organizations;
+            self.accounts.save(&self.session).await?;
         }
 
-        let mut headers = reqwest::header::HeaderMap::new();
-        headers.insert(
-            reqwest::header::AUTHORIZATION,
-            reqwest::header::HeaderValue::from_str(&format!("Bearer {}", self.token)).unwrap(),
-        );
+        Ok(())
+    }
 
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()
-            .unwrap();
+    /// The error that sends the user to the login form, and back to the current page afterwards.
+    pub(super) fn needs_auth(&self) -> Error {
+        Error::NeedsAuth {
+            redirect_to: Some(self.redirect_to.to_string()),
+        }
+    }
 
-        lru.insert(self.token.clone(), client.clone());
+    /// An API client for this token, or `Error::NeedsAuth` if the user isn't logged in.
+    pub(super) fn api(&self) -> Result<SentryApi, Error> {
+        let needs_auth = || self.needs_auth();
+        let token = self.decrypt().ok_or_else(needs_auth)?;
+        // the instance may have been removed from the configuration since
+        let upstream = self
+            .account()
+            .and_then(|account| Upstream::find(&account.upstream))
+            .ok_or_else(needs_auth)?;
 
-        Ok(client)
+        Ok(SentryApi::new(
+            client(&token),
+            &token,
+            upstream.clone(),
+            Some(self.redirect_to.to_string()),
+        ))
     }
 }
 
+/// A client that sends `token`. Clients are reused so that connections are pooled across requests
+/// of the same user.
+fn client(token: &str) -> reqwest::Client {
+    // keyed by hash so that plaintext tokens don't linger in memory any longer than necessary
+    static CLIENT: Mutex<Option<LruMap<String, reqwest::Client>>> = Mutex::new(None);
+
+    let key = token_hash(token);
+    let mut guard = CLIENT.lock().unwrap();
+    let lru = guard.get_or_insert_with(|| LruMap::new(ByLength::new(50)));
+
+    let cached = lru.get(&key).cloned();
+    metrics::track_client_cache(cached.is_some());
+    if let Some(client) = cached {
+        return client;
+    }
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    let mut auth_value =
+        reqwest::header::HeaderValue::from_str(&format!("Bearer {token}")).unwrap();
+    auth_value.set_sensitive(true);
+    headers.insert(reqwest::header::AUTHORIZATION, auth_value);
+
+    let client = reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap();
+
+    lru.insert(key, client.clone());
+
+    client
+}
+
 #[async_trait]
 impl<S> FromRequestParts<S> for SentryToken
 where
@@ -88,13 +570,166 @@ where
     type Rejection = (StatusCode, &'static str);
 
     async fn from_request_parts(req: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let redirect_to = Uri::from_request_parts(req, state).await.unwrap();
+        // includes the base path, unlike `Uri`
+        let OriginalUri(redirect_to) = OriginalUri::from_request_parts(req, state).await.unwrap();
         let session = Session::from_request_parts(req, state).await?;
-        let token: String = session
-            .get(SESSION_COOKIE_KEY)
+        let mut accounts = Accounts::load(&session).await;
+
+        let org = RawPathParams::from_request_parts(req, state)
+            .await
+            .ok()
+            .and_then(|params| {
+                params
+                    .iter()
+                    .find(|(key, _)| *key == "org")
+                    .map(|(_, value)| value.to_owned())
+            });
+
+        let account = match org {
+            Some(org) => accounts.for_organization(&org),
+            None => accounts.active(),
+        };
+        let current = account.map(|account| account.id.clone());
+
+        if let Some(account) = current.as_deref().and_then(|id| accounts.get_mut(id)) {
+            if account.refresh().await {
+                if let Err(e) = accounts.save(&session).await {
+                    tracing::error!("failed to store refreshed oauth token: {}", e);
+                }
+            }
+        }
+
+        Ok(SentryToken {
+            session,
+            accounts,
+            current,
+            redirect_to,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tower_sessions::MemoryStore;
+
+    use super::*;
+
+    fn account(id: &str, upstream: &str, user: Option<(&str, &str)>) -> Account {
+        Account {
+            id: id.to_owned(),
+            upstream: upstream.to_owned(),
+            encrypted_token: String::new(),
+            identity: Identity {
+                user: user.map(|(id, name)| ApiUser {
+                    id: id.to_owned(),
+                    name: name.to_owned(),
+                    email: format!("{name}@example.com"),
+                    avatar_url: None,
+                }),
+                scopes: Vec::new(),
+            },
+            grant: None,
+            organizations: Vec::new(),
+        }
+    }
+
+    fn account_ids(accounts: &Accounts) -> Vec<&str> {
+        accounts.accounts.iter().map(|a| a.id.as_str()).collect()
+    }
+
+    #[test]
+    fn add_same_user_again() {
+        let mut accounts = Accounts::default();
+        accounts.add(account("a", "https://sentry.io", Some(("1", "ann"))));
+        accounts.add(account("b", "https://sentry.io", Some(("2", "bob"))));
+        accounts.add(account("c", "https://sentry.io", Some(("1", "ann"))));
+
+        assert_eq!(account_ids(&accounts), ["b", "c"]);
+        assert_eq!(accounts.active().unwrap().id, "c");
+    }
+
+    #[test]
+    fn add_users_with_the_same_name() {
+        let mut accounts = Accounts::default();
+        accounts.add(account("a", "https://sentry.io", Some(("1", "ann"))));
+        accounts.add(account("b", "https://sentry.io", Some(("2", "ann"))));
+        // same ID, but a different instance
+        accounts.add(account(
+            "c",
+            "https://sentry.example.com",
+            Some(("1", "ann")),
+        ));
+
+        assert_eq!(account_ids(&accounts), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn add_organization_tokens() {
+        let mut accounts = Accounts::default();
+        accounts.add(account("a", "https://sentry.io", None));
+        accounts.add(account("b", "https://sentry.io", None));
+
+        assert_eq!(account_ids(&accounts), ["a", "b"]);
+        assert_eq!(accounts.active().unwrap().id, "b");
+    }
+
+    async fn session_with_pending_login() -> Session {
+        let session = Session::new(None, Arc::new(MemoryStore::default()), None);
+        let pending = PendingOAuthLogin {
+            state: "expected".to_owned(),
+            redirect_to: Some("/acme".to_owned()),
+        };
+        session
+            .insert(SESSION_OAUTH_STATE_KEY, pending)
+            .await
+            .unwrap();
+        session
+    }
+
+    #[tokio::test]
+    async fn pending_login_matching_state() {
+        let session = session_with_pending_login().await;
+
+        let pending = take_pending_login(&session, Some("expected"))
             .await
             .unwrap()
-            .unwrap_or_default();
-        Ok(SentryToken { token, redirect_to })
+            .unwrap();
+        assert_eq!(pending.redirect_to.as_deref(), Some("/acme"));
+
+        // the state can only be used once
+        let pending = take_pending_login(&session, Some("expected"))
+            .await
+            .unwrap();
+        assert!(pending.is_none());
+    }
+
+    #[tokio::test]
+    async fn pending_login_wrong_state() {
+        let session = session_with_pending_login().await;
+
+        let pending = take_pending_login(&session, Some("forged")).await.unwrap();
+        assert!(pending.is_none());
+
+        // a failed attempt uses up the state as well
+        let pending = take_pending_login(&session, Some("expected"))
+            .await
+            .unwrap();
+        assert!(pending.is_none());
+    }
+
+    #[tokio::test]
+    async fn pending_login_missing_state() {
+        let session = session_with_pending_login().await;
+        let pending = take_pending_login(&session, None).await.unwrap();
+        assert!(pending.is_none());
+
+        // a callback without `oauth_login` first
+        let session = Session::new(None, Arc::new(MemoryStore::default()), None);
+        let pending = take_pending_login(&session, Some("expected"))
+            .await
+            .unwrap();
+        assert!(pending.is_none());
     }
 }
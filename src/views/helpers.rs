@@ -1,26 +1,166 @@
+use axum::extract::Request;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 use human_repr::HumanCount;
 use jiff::{SpanRound, Timestamp, Unit};
 use maud::Markup;
 
+use crate::routes::{
+    is_own_url, url, AddAccount, AdminAuditLog, Auth, Index, Logout, OAuthLogin, SwitchAccount,
+};
+use crate::sentry_api::RateLimit;
+use crate::views::auth::{AccountSummary, Identity};
+use crate::{csp, csrf, OAuthApp, Upstream};
+
 pub use maud::html;
 
 #[derive(Default)]
 pub struct LayoutOptions {
     pub title: String,
     pub header: Option<Markup>,
+    /// shown as a warning if set, see `SentryApi::low_rate_limit`
+    pub rate_limit: Option<RateLimit>,
+    /// shown in the account switcher next to the logout button, see `SentryToken::accounts`
+    pub accounts: Vec<AccountSummary>,
 }
 
 pub fn wrap_admin_template(mut opt: LayoutOptions, content: Markup) -> Markup {
+    let current = opt.accounts.iter().find(|account| account.current);
     opt.header = Some(html! {
-        form method="post" action="/auth/logout" {
-            // https://github.com/picocss/pico/issues/496
-            button.outline.secondary type="submit" style="margin: 0 auto; width: auto; float: right; font-size: 0.6em; padding: 0.6em" { "Logout"}
+        @if let Some(current) = current {
+            form method="post" action=(url(Logout)) {
+                (csrf_field())
+                input type="hidden" name="account" value=(current.id);
+                // https://github.com/picocss/pico/issues/496
+                button.logout.outline.secondary type="submit" { "Logout"}
+            }
+
+            details.dropdown.accounts {
+                summary { (render_identity(&current.identity)) }
+                ul dir="rtl" {
+                    @for account in opt.accounts.iter().filter(|account| !account.current) {
+                        li {
+                            form.switch-account method="post" action=(url(SwitchAccount)) {
+                                (csrf_field())
+                                input type="hidden" name="account" value=(account.id);
+                                button type="submit" { (account.name) }
+                            }
+                        }
+                    }
+                    li { a href=(url(AddAccount)) { "add account" } }
+                    @if current.may_view_audit_log {
+                        li { a href=(url(AdminAuditLog)) { "audit log" } }
+                    }
+                }
+            }
         }
     });
+    let content = html! {
+        @if let Some(rate_limit) = opt.rate_limit {
+            p.rate-limit-warning {
+                "running low on sentry API quota: "
+                (rate_limit.remaining) " of " (rate_limit.limit) " requests left"
+                @if let Some(reset) = rate_limit.reset {
+                    ", refilled in " ((reset.as_second() - Timestamp::now().as_second()).max(0)) " seconds"
+                }
+                "."
+            }
+        }
+
+        (content)
+    };
+
     wrap_template(opt, content)
 }
 
+fn render_identity(identity: &Identity) -> Markup {
+    let scopes = if identity.scopes.is_empty() {
+        "no scopes".to_owned()
+    } else {
+        format!("scopes: {}", identity.scopes.join(", "))
+    };
+
+    html! {
+        span.identity data-tooltip=(scopes) data-placement="bottom" {
+            @if let Some(ref user) = identity.user {
+                @if let Some(ref avatar_url) = user.avatar_url {
+                    img.avatar src=(avatar_url) alt="";
+                }
+                span title=(user.email) {
+                    @if user.name.is_empty() { (user.email) } @else { (user.name) }
+                }
+            } @else {
+                "organization token"
+            }
+        }
+    }
+}
+
+/// Hidden field to include in every form that is POSTed, see `csrf::verify`.
+pub fn csrf_field() -> Markup {
+    html! {
+        input type="hidden" name=(csrf::FORM_FIELD) value=(csrf::token());
+    }
+}
+
+/// The form to log in with an API token. Also shown when Sentry rejected the current token.
+pub fn login_form(redirect_to: Option<&str>, error: Option<&str>) -> Markup {
+    // comes from the query string, and would be redirected to after logging in
+    let redirect_to = redirect_to.filter(|target| is_own_url(target));
+
+    html! {
+        form.login method="post" action=(url(Auth)) {
+            (csrf_field())
+            @if let Some(redirect_to) = redirect_to {
+                input type="hidden" name="redirect_to" value=(redirect_to);
+            }
+
+            @if Upstream::all().len() > 1 {
+                select name="upstream" aria-label="sentry instance" {
+                    @for upstream in Upstream::all() {
+                        option value=(upstream.base_url()) { (upstream.host()) }
+                    }
+                }
+            }
+
+            fieldset role="group" {
+                input type="password" name="token" placeholder="your API token" aria-invalid=[error.map(|_| "true")] aria-describedby="login-help";
+                input type="submit" value="Login";
+            }
+
+            small #login-help {
+                @if let Some(error) = error {
+                    (error)
+                } @else {
+                    "get a user API token from Sentry to view issues"
+                }
+            }
+        }
+
+        @if OAuthApp::get().is_some() {
+            // not boosted, as htmx can't follow the redirect to sentry. only works for the
+            // default instance
+            a.oauth-login role="button" hx-boost="false" href=(oauth_login_url(redirect_to)) {
+                "or log in with sentry"
+            }
+        }
+    }
+}
+
+fn oauth_login_url(redirect_to: Option<&str>) -> String {
+    match redirect_to {
+        Some(redirect_to) => format!(
+            "{}?{}",
+            url(OAuthLogin),
+            serde_urlencoded::to_string([("redirect_to", redirect_to)])
+                .expect("params are strings")
+        ),
+        None => url(OAuthLogin),
+    }
+}
+
 pub fn wrap_template(opt: LayoutOptions, content: Markup) -> Markup {
     html! {
         (maud::DOCTYPE)
@@ -35,21 +175,24 @@ pub fn wrap_template(opt: LayoutOptions, content: Markup) -> Markup {
             meta charset="utf-8";
             meta name="viewport" content="width=device-width, initial-scale=1";
             meta name="color-scheme" content="light dark";
-            link rel="stylesheet" href="/style.css";
+            link rel="stylesheet" href=(url("/style.css"));
+
+            meta name="htmx-config" content=(htmx_config());
 
-            script src="/htmx.js" {}
-            script src="/htmx.preload.js" {}
+            script src=(url("/htmx.js")) {}
+            script src=(url("/htmx.preload.js")) {}
+            script src=(url("/app.js")) {}
         }
 
-        body hx-boost="true" hx-indicator="#spinner" hx-ext="preload" {
+        body hx-boost="true" hx-indicator="#spinner" hx-ext="preload" hx-headers=(csrf_headers()) {
             header.container {
                 div.grid {
                     div {
                         h1 {
-                            a.secondary preload="mouseover" href="/" { "sentry.mobi" }
+                            a.secondary preload="mouseover" href=(url(Index)) { "sentry.mobi" }
                             " "
                                 small.htmx-indicator id="spinner" aria-busy="true" {
-                                    span style="display: none" {
+                                    span.hidden {
                                         "is loading"
                                     }
                                 }
@@ -69,7 +212,49 @@ pub fn wrap_template(opt: LayoutOptions, content: Markup) -> Markup {
     }
 }
 
-pub const REGION_DOMAINS: &[&str] = &["us.sentry.io", "de.sentry.io"];
+fn htmx_config() -> String {
+    serde_json::json!({
+        // also swap in error pages, htmx ignores 4xx and 5xx responses by default
+        "responseHandling": [{"code": "204", "swap": false}, {"code": "...", "swap": true}],
+        // htmx adds a style element for its indicators, which needs to pass the CSP
+        "inlineStyleNonce": csp::nonce(),
+    })
+    .to_string()
+}
+
+/// Middleware that makes error pages show up when htmx requested only a part of a page, like the
+/// status of an issue. The error page doesn't contain that part, so htmx would swap in nothing and
+/// the control would vanish. Instead, the error page replaces the page's content.
+pub async fn retarget_htmx_errors(request: Request, next: Next) -> Response {
+    let is_hx = request.headers().contains_key("hx-request");
+    let mut response = next.run(request).await;
+    if !is_hx || !(response.status().is_client_error() || response.status().is_server_error()) {
+        return response;
+    }
+
+    let is_html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+
+    let headers = response.headers_mut();
+    if is_html {
+        headers.insert("hx-retarget", HeaderValue::from_static("main"));
+        headers.insert("hx-reselect", HeaderValue::from_static("main"));
+        headers.insert("hx-reswap", HeaderValue::from_static("outerHTML"));
+    } else {
+        // e.g. axum's plain text rejections, there is nothing to show. at least keep the page
+        // as it is.
+        headers.insert("hx-reswap", HeaderValue::from_static("none"));
+    }
+    response
+}
+
+/// Also send the CSRF token with htmx requests that don't come from one of our forms.
+fn csrf_headers() -> String {
+    serde_json::json!({ csrf::HEADER: csrf::token() }).to_string()
+}
 
 pub fn print_relative_time(ts: Timestamp) -> Markup {
     html! {
@@ -97,8 +282,8 @@ pub fn print_relative_time(ts: Timestamp) -> Markup {
 
 pub fn breadcrumbs(url: &str, h2_content: Markup) -> Markup {
     html! {
-        div style="margin-bottom: var(--pico-typography-spacing-vertical)" {
-            h2 style="font-size: 1em; display: inline" {
+        div.breadcrumbs {
+            h2 {
                 (h2_content)
             }
 
@@ -1,17 +1,24 @@
+use std::time::Duration;
+
 use axum::extract::Query;
 use axum::response::IntoResponse;
+use futures::future::join_all;
 use serde::Deserialize;
 
-use crate::routes::OrganizationDetails;
+use crate::routes::{url, OrganizationDetails};
+use crate::upstream::Upstream;
 use crate::views::helpers::{
-    html, wrap_admin_template, wrap_template, Html, LayoutOptions, REGION_DOMAINS,
+    html, login_form, wrap_admin_template, wrap_template, Html, LayoutOptions,
 };
 use crate::{Error, SentryToken};
 
+/// How long to wait for a single region before showing the organizations of the others.
+const REGION_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Deserialize)]
 pub struct RedirectTo {
     #[serde(default)]
-    redirect_to: Option<String>,
+    pub redirect_to: Option<String>,
 }
 
 pub async fn index(
@@ -19,25 +26,10 @@ pub async fn index(
     token: SentryToken,
     Query(params): Query<RedirectTo>,
 ) -> Result<impl IntoResponse, Error> {
-    if token.token.is_empty() {
+    if !token.is_logged_in() {
         Ok(Html(wrap_template(
             LayoutOptions::default(),
-            html! {
-                form.login method="post" action="/auth" {
-                    @if let Some(redirect_to) = params.redirect_to {
-                        input type="hidden" name="redirect_to" value=(redirect_to);
-                    }
-
-                    fieldset role="group" {
-                        input type="password" name="token" placeholder="your API token";
-                        input type="submit" value="Login";
-                    }
-
-                    small {
-                        "get a user API token from Sentry to view issues"
-                    }
-                }
-            },
+            login_form(params.redirect_to.as_deref(), None),
         ))
         .into_response())
     } else {
@@ -45,43 +37,40 @@ pub async fn index(
     }
 }
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ApiOrganization {
-    name: String,
-    slug: String,
-    links: Links,
-    #[serde(default)]
-    is_bookmarked: bool,
-}
+async fn organization_overview(mut token: SentryToken) -> Result<impl IntoResponse, Error> {
+    let api = token.api()?;
+    let regions = api.list_regions().await;
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Links {
-    #[serde(default)]
-    region_url: String,
-}
+    let api = &api;
+    let results = join_all(regions.iter().map(|region_url| async move {
+        let orgs = api.list_organizations(region_url);
+        (region_url, tokio::time::timeout(REGION_TIMEOUT, orgs).await)
+    }))
+    .await;
 
-impl ApiOrganization {
-    fn region_domain(&self) -> &str {
-        self.links.region_url.strip_prefix("https://").unwrap_or("")
+    let mut response = Vec::new();
+    let mut region_errors = Vec::new();
+
+    for (region_url, result) in results {
+        match result {
+            Ok(Ok(orgs)) => response.extend(orgs),
+            Ok(Err(e)) => region_errors.push((region_url, Some(e))),
+            Err(_) => region_errors.push((region_url, None)),
+        }
     }
-}
 
-async fn organization_overview(token: SentryToken) -> Result<impl IntoResponse, Error> {
-    let client = token.client()?;
-    let mut response = Vec::new();
+    // if nothing worked at all, it's probably not the region's fault (e.g. the token was revoked)
+    if region_errors.len() == regions.len() {
+        if let Some((_, e)) = region_errors.iter_mut().find(|(_, e)| e.is_some()) {
+            return Err(e.take().unwrap());
+        }
+    }
 
-    for domain in REGION_DOMAINS {
-        let region_response: Vec<ApiOrganization> = client
-            .get(format!("https://{domain}/api/0/organizations/"))
-            .send()
-            .await?
-            .error_for_status()?
-            .json()
+    // a partial list would make us forget organizations of the failed regions
+    if region_errors.is_empty() {
+        token
+            .remember_organizations(response.iter().map(|o| o.slug.clone()).collect())
             .await?;
-
-        response.extend(region_response);
     }
 
     response.sort_by_key(|o| !o.is_bookmarked);
@@ -89,15 +78,30 @@ async fn organization_overview(token: SentryToken) -> Result<impl IntoResponse,
     let body = wrap_admin_template(
         LayoutOptions {
             title: "organizations".to_owned(),
+            rate_limit: api.low_rate_limit(),
+            accounts: token.accounts(),
             ..Default::default()
         },
         html! {
-            h2 { "organizations" }
+            h2 { "organizations " small.secondary { "(" (response.len()) ")" } }
+
+            @for (region_url, error) in &region_errors {
+                p.region-warning {
+                    "could not load organizations from "
+                    code { (Upstream::new(region_url).host()) }
+                    ": "
+                    @if let Some(error) = error {
+                        (error)
+                    } @else {
+                        "timed out"
+                    }
+                }
+            }
 
             ul {
                 @for org in response {
                     li {
-                        a preload="mouseover" href=(OrganizationDetails { org: org.slug.clone() }) {
+                        a preload="mouseover" href=(url(OrganizationDetails { org: org.slug.clone() })) {
                             (org.name)
                         }
 
@@ -106,7 +110,7 @@ async fn organization_overview(token: SentryToken) -> Result<impl IntoResponse,
                         }
 
                         small {
-                            " (" (org.region_domain()) "/" (org.slug) ")"
+                            " (" (org.region_domain(api.upstream())) "/" (org.slug) ")"
                         }
                     }
                 }
@@ -1,168 +1,30 @@
-use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
 
+use axum::extract::ConnectInfo;
 use axum::response::{IntoResponse, Redirect};
 use axum::Form;
 use axum_htmx::HxRequest;
 use jiff::Timestamp;
-use maud::{html, Markup, PreEscaped};
-use serde::{Deserialize, Serialize};
+use maud::{html, Markup};
+use serde::Deserialize;
 
-use crate::routes::IssueDetails;
+use crate::audit::AuditEntry;
+use crate::routes::{
+    url, IssueAssignee, IssueDetails, IssuePriority, OrganizationDetails, ProjectDetails,
+};
+use crate::sentry_api::{
+    ApiAssignee, ApiEventEntry, ApiIssue, ApiRelease, ApiRepository, ApiTeam, ApiUpdate, ApiUser,
+    KnownEventEntry, Stacktrace,
+};
 use crate::views::helpers::{
-    breadcrumbs, event_count, print_relative_time, wrap_admin_template, Html, LayoutOptions,
+    breadcrumbs, csrf_field, event_count, print_relative_time, wrap_admin_template, Html,
+    LayoutOptions,
 };
-use crate::{Error, SentryToken};
+use crate::{AuditLog, Error, SentryApi, SentryToken};
 
 const MAX_BREADCRUMBS: usize = 20;
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ApiIssue {
-    title: String,
-    first_seen: Timestamp,
-    last_seen: Timestamp,
-    status: String,
-    level: String,
-    permalink: String,
-    short_id: String,
-    #[serde(default)]
-    logger: Option<String>,
-    count: String,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ApiEvent {
-    #[serde(rename = "dateCreated")]
-    timestamp: Timestamp,
-
-    #[serde(default)]
-    tags: Vec<ApiTag>,
-
-    entries: Vec<ApiEventEntry>,
-}
-
-#[derive(Deserialize)]
-#[serde(untagged)]
-enum ApiEventEntry {
-    Known(KnownEventEntry),
-    Other {
-        #[serde(rename = "type")]
-        ty: String,
-        #[serde(flatten)]
-        attributes: BTreeMap<String, serde_json::Value>,
-    },
-}
-
-#[derive(Deserialize)]
-#[serde(tag = "type", rename_all = "camelCase")]
-enum KnownEventEntry {
-    Message { data: MessageData },
-    Breadcrumbs { data: BreadcrumbData },
-    Threads { data: ThreadsData },
-    Exception { data: ExceptionData },
-    Request { data: RequestData },
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct MessageData {
-    formatted: String,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct BreadcrumbData {
-    values: Vec<Breadcrumb>,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Breadcrumb {
-    timestamp: Timestamp,
-    level: String,
-    message: String,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ExceptionData {
-    values: Vec<Exception>,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Exception {
-    #[serde(rename = "type", default)]
-    ty: String,
-    #[serde(default)]
-    value: String,
-    #[serde(default)]
-    stacktrace: Option<Stacktrace>,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ThreadsData {
-    values: Vec<Thread>,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Thread {
-    #[serde(default)]
-    crashed: bool,
-    #[serde(default)]
-    current: bool,
-    #[serde(default)]
-    stacktrace: Option<Stacktrace>,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Stacktrace {
-    frames: Vec<Frame>,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Frame {
-    #[serde(default)]
-    in_app: bool,
-
-    #[serde(default)]
-    filename: Option<String>,
-    #[serde(default)]
-    function: Option<String>,
-    #[serde(default)]
-    line_no: Option<u64>,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ApiTag {
-    key: String,
-    value: String,
-}
-
-#[derive(Deserialize)]
-struct RequestData {
-    method: String,
-    url: String,
-    //#[serde(default)]
-    //query: Vec<String>,
-    //#[serde(default)]
-    //fragment: Option<String>,
-    #[serde(default)]
-    headers: Vec<(String, String)>,
-    #[serde(default)]
-    env: BTreeMap<String, String>,
-    #[serde(default)]
-    cookies: Vec<(String, String)>,
-    #[serde(default)]
-    data: Option<serde_json::Value>,
-}
-
 pub async fn issue_details(
     IssueDetails {
         org,
@@ -171,48 +33,33 @@ pub async fn issue_details(
     }: IssueDetails,
     token: SentryToken,
 ) -> Result<impl IntoResponse, Error> {
-    let client = token.client()?;
+    let api = token.api()?;
 
     let (issue_response, event_response) = tokio::try_join!(
-        async {
-            client
-                .get(format!(
-                    // XXX: the docs here are out of date: https://docs.sentry.io/api/events/retrieve-an-issue/
-                    "https://sentry.io/api/0/organizations/{org}/issues/{issue_id}/"
-                ))
-                .send()
-                .await?
-                .error_for_status()?
-                .json::<ApiIssue>()
-                .await
-        },
-        async {
-            client
-                .get(format!(
-                    "https://sentry.io/api/0/organizations/{org}/issues/{issue_id}/events/latest/"
-                ))
-                .send()
-                .await?
-                .error_for_status()?
-                .json::<ApiEvent>()
-                .await
-        }
+        api.get_issue(&org, &issue_id),
+        api.latest_event(&org, &issue_id)
     )?;
 
-    let title = issue_response.title;
+    let (assignees, resolve_choices) = tokio::join!(
+        assignee_choices(&api, &org, &proj, &issue_response.project.id),
+        resolve_choices(&api, &org, &proj, &issue_response),
+    );
+    let title = issue_response.title.clone();
 
     let body = wrap_admin_template(
         LayoutOptions {
             title: format!("{title} - {org}/{proj}"),
+            rate_limit: api.low_rate_limit(),
+            accounts: token.accounts(),
             ..Default::default()
         },
         html! {
             (breadcrumbs(&issue_response.permalink, html! {
-                a href=(crate::routes::OrganizationDetails { org: org.clone() }) {
+                a href=(url(OrganizationDetails { org: org.clone() })) {
                     (org)
                 }
                 "/"
-                a href=(crate::routes::ProjectDetails { org: org.clone(), proj: proj.clone() }) {
+                a href=(url(ProjectDetails { org: org.clone(), proj: proj.clone() })) {
                     (proj)
                 }
                 "/"
@@ -220,13 +67,25 @@ pub async fn issue_details(
             }))
 
             div.grid {
-                h2 style="grid-column-end: span 2" {
+                h2.issue-title {
                     span data-level=(issue_response.level) { (issue_response.level) ": " }
                     (title)
                 }
 
                 div {
-                    (render_button_status(&issue_response.status))
+                    (render_button_status(&issue_response, &resolve_choices))
+                    @if let Some(ref priority) = issue_response.priority {
+                        (render_priority(
+                            IssuePriority { org: org.clone(), proj: proj.clone(), issue_id: issue_id.clone() },
+                            priority,
+                        ))
+                    }
+                    (render_assignee(
+                        IssueAssignee { org: org.clone(), proj: proj.clone(), issue_id: issue_id.clone() },
+                        issue_response.assigned_to.as_ref(),
+                        token.current_user(),
+                        &assignees,
+                    ))
                 }
             }
 
@@ -241,34 +100,6 @@ pub async fn issue_details(
                 code { (print_relative_time(issue_response.last_seen)) } " ago. "
             } }
 
-            style {
-                (PreEscaped(r#"
-                :scope {
-                    .event-entries > details {
-                        padding: 0.5rem;
-                        padding-left: 1rem;
-                        border-radius: var(--pico-border-radius);
-                    }
-
-                    .event-entries > details[open] {
-                        background: var(--pico-card-background-color);
-                    }
-
-                    .event-entries > details > summary {
-                        margin-left: -0.5rem;
-                    }
-
-                    .event-entries > details > summary::after {
-                        margin-top: 0.33rem;
-                    }
-
-                    .event-entries > details > summary > h3 {
-                        display: inline;
-                    }
-                }
-                "#))
-            }
-
             div.event-entries {
                 @for entry in event_response.entries {
                     @match entry {
@@ -483,7 +314,7 @@ pub async fn issue_details(
                                     " ("
                                     a.secondary href=(
                                         format!("{}?query={}:{}",
-                                            crate::routes::ProjectDetails { org: org.clone(), proj: proj.clone() },
+                                            url(ProjectDetails { org: org.clone(), proj: proj.clone() }),
                                             tag.key, tag.value
                                         )
                                     ) {
@@ -506,26 +337,6 @@ fn render_stacktrace(stacktrace: &Stacktrace) -> Markup {
     html! {
         i { "most recent (crashing frame) to least recent (main function)" }
 
-        style {
-            (PreEscaped(r#"
-            :scope {
-                .system-frame {
-                    font-style: italic;
-                    display: none;
-                }
-
-                .show-system-frames-chk:has(:checked) + ul > .system-frame {
-                    display: block;
-                }
-
-                .stacktrace > li {
-                    font-size: 0.7em;
-                    list-style: none;
-                }
-            }
-            "#))
-        }
-
         label.show-system-frames-chk {
             input type="checkbox" switch="";
             "show system frames"
@@ -549,108 +360,517 @@ fn render_stacktrace(stacktrace: &Stacktrace) -> Markup {
     }
 }
 
-/// the status as sent from the frontend
-#[derive(Deserialize, Copy, Clone)]
-#[serde(rename_all = "snake_case")]
+/// How long the archive dropdown offers to archive for, in minutes.
+const ARCHIVE_DURATIONS: [u32; 4] = [30, 2 * 60, 24 * 60, 7 * 24 * 60];
+
+/// How many more events or affected users the archive dropdown offers to wait for.
+const ARCHIVE_THRESHOLDS: [u32; 3] = [10, 100, 1000];
+
+/// the status as sent from the frontend. resolving in a release and archiving until a condition
+/// is met carry the release or threshold after a colon, e.g. `archived_for_minutes:30`.
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
 pub enum StatusParam {
     Unresolved,
     Resolved,
     ResolvedInNextRelease,
+    ResolvedInRelease(String),
+    /// the commit is in `UpdateParams`, as it is typed in
+    ResolvedInCommit,
     ArchivedUntilEscalating,
     ArchivedForever,
+    ArchivedForMinutes(u32),
+    ArchivedUntilCount(u32),
+    ArchivedUntilUserCount(u32),
 }
 
-#[derive(Serialize, Default)]
-#[serde(rename_all = "camelCase")]
-struct ApiUpdate {
-    status: String,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    substatus: Option<String>,
-    status_details: BTreeMap<String, bool>,
+impl TryFrom<String> for StatusParam {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (name, argument) = match value.split_once(':') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (value.as_str(), None),
+        };
+        // archiving until something happens zero more times would unarchive right away
+        let threshold = |threshold: &str| {
+            threshold
+                .parse()
+                .map(NonZeroU32::get)
+                .map_err(|e| format!("invalid threshold {threshold:?}: {e}"))
+        };
+
+        Ok(match (name, argument) {
+            ("unresolved", None) => StatusParam::Unresolved,
+            ("resolved", None) => StatusParam::Resolved,
+            ("resolved_in_next_release", None) => StatusParam::ResolvedInNextRelease,
+            // versions may contain colons themselves
+            ("resolved_in_release", Some(version)) if !version.is_empty() => {
+                StatusParam::ResolvedInRelease(version.to_owned())
+            }
+            ("resolved_in_commit", None) => StatusParam::ResolvedInCommit,
+            ("archived_until_escalating", None) => StatusParam::ArchivedUntilEscalating,
+            ("archived_forever", None) => StatusParam::ArchivedForever,
+            ("archived_for_minutes", Some(minutes)) => {
+                StatusParam::ArchivedForMinutes(threshold(minutes)?)
+            }
+            ("archived_until_count", Some(count)) => {
+                StatusParam::ArchivedUntilCount(threshold(count)?)
+            }
+            ("archived_until_user_count", Some(count)) => {
+                StatusParam::ArchivedUntilUserCount(threshold(count)?)
+            }
+            _ => return Err(format!("unknown status {value:?}")),
+        })
+    }
 }
 
 #[derive(Deserialize)]
 pub struct UpdateParams {
     status: StatusParam,
+    /// SHA, for `StatusParam::ResolvedInCommit`
+    #[serde(default)]
+    commit: String,
+    /// name of the repository the commit is in, for `StatusParam::ResolvedInCommit`
+    #[serde(default)]
+    repository: String,
 }
 
 impl UpdateParams {
     /// Convert to the structure that our API expects for status updates.
-    fn to_api(self) -> ApiUpdate {
-        match self.status {
+    fn to_api(&self) -> Result<ApiUpdate, Error> {
+        let archived_until_condition = |key: &str, threshold: u32| ApiUpdate {
+            status: Some("ignored".to_string()),
+            substatus: Some("archived_until_condition_met".to_string()),
+            status_details: [(key.to_string(), threshold.into())].into_iter().collect(),
+            ..Default::default()
+        };
+
+        let resolved_with = |key: &str, details: serde_json::Value| ApiUpdate {
+            status: Some("resolved".to_string()),
+            status_details: [(key.to_string(), details)].into_iter().collect(),
+            ..Default::default()
+        };
+
+        Ok(match self.status {
             StatusParam::Unresolved => ApiUpdate {
-                status: "unresolved".to_string(),
+                status: Some("unresolved".to_string()),
                 ..Default::default()
             },
 
             // resolved
             StatusParam::Resolved => ApiUpdate {
-                status: "resolved".to_string(),
-                ..Default::default()
-            },
-            StatusParam::ResolvedInNextRelease => ApiUpdate {
-                status: "resolved".to_string(),
-                status_details: [("inNextRelease".to_string(), true)].into_iter().collect(),
+                status: Some("resolved".to_string()),
                 ..Default::default()
             },
+            StatusParam::ResolvedInNextRelease => resolved_with("inNextRelease", true.into()),
+            StatusParam::ResolvedInRelease(ref version) => {
+                resolved_with("inRelease", version.as_str().into())
+            }
+            StatusParam::ResolvedInCommit => {
+                let commit = self.commit.trim();
+                if commit.is_empty() || self.repository.is_empty() {
+                    return Err(Error::InvalidForm("enter a commit and pick its repository"));
+                }
+                resolved_with(
+                    "inCommit",
+                    serde_json::json!({ "commit": commit, "repository": self.repository }),
+                )
+            }
 
             // archived
             // i guess somebody decided statusDetails is no longer good and just started adding
             // substatus?
             StatusParam::ArchivedUntilEscalating => ApiUpdate {
-                status: "ignored".to_string(),
+                status: Some("ignored".to_string()),
                 substatus: Some("archived_until_escalating".to_string()),
                 ..Default::default()
             },
             StatusParam::ArchivedForever => ApiUpdate {
-                status: "ignored".to_string(),
+                status: Some("ignored".to_string()),
                 substatus: Some("archived_forever".to_string()),
                 ..Default::default()
             },
-        }
+            // ...but the conditions still go into statusDetails
+            StatusParam::ArchivedForMinutes(minutes) => {
+                archived_until_condition("ignoreDuration", minutes)
+            }
+            StatusParam::ArchivedUntilCount(count) => {
+                archived_until_condition("ignoreCount", count)
+            }
+            StatusParam::ArchivedUntilUserCount(count) => {
+                archived_until_condition("ignoreUserCount", count)
+            }
+        })
     }
 }
 
 pub async fn update_issue_details(
-    IssueDetails {
-        org,
-        proj,
-        issue_id,
-    }: IssueDetails,
+    route: IssueDetails,
     HxRequest(is_hx): HxRequest,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     token: SentryToken,
     Form(params): Form<UpdateParams>,
 ) -> Result<impl IntoResponse, Error> {
-    let client = token.client()?;
+    let api = token.api()?;
+    let api_update = params.to_api()?;
+    let issue = apply_update(
+        &token,
+        &api,
+        &route,
+        connect_info,
+        &api_update,
+        ("status", describe_status),
+    )
+    .await?;
+
+    if is_hx {
+        let choices = resolve_choices(&api, &route.org, &route.proj, &issue).await;
+        Ok(Html(render_button_status(&issue, &choices)).into_response())
+    } else {
+        Ok(Redirect::to(&url(route)).into_response())
+    }
+}
+
+#[derive(Deserialize, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum PriorityParam {
+    High,
+    Medium,
+    Low,
+}
+
+impl PriorityParam {
+    fn as_str(self) -> &'static str {
+        match self {
+            PriorityParam::High => "high",
+            PriorityParam::Medium => "medium",
+            PriorityParam::Low => "low",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PriorityParams {
+    priority: PriorityParam,
+}
 
-    let api_update = params.to_api();
+pub async fn update_priority(
+    route: IssuePriority,
+    HxRequest(is_hx): HxRequest,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    token: SentryToken,
+    Form(params): Form<PriorityParams>,
+) -> Result<impl IntoResponse, Error> {
+    let api = token.api()?;
+    let issue_route = IssueDetails::from(&route);
+    let api_update = ApiUpdate {
+        priority: Some(params.priority.as_str().to_owned()),
+        ..Default::default()
+    };
+    let issue = apply_update(
+        &token,
+        &api,
+        &issue_route,
+        connect_info,
+        &api_update,
+        ("priority", |issue| {
+            issue.priority.clone().unwrap_or_default()
+        }),
+    )
+    .await?;
 
-    client
-        .put(format!(
-            // XXX: outdated docs: https://docs.sentry.io/api/events/update-an-issue/
-            "https://sentry.io/api/0/organizations/{org}/issues/{issue_id}/"
+    if is_hx {
+        let priority = issue.priority.as_deref();
+        Ok(Html(render_priority(
+            route,
+            priority.unwrap_or(params.priority.as_str()),
         ))
-        .json(&api_update)
-        .send()
-        .await?
-        .error_for_status()?;
+        .into_response())
+    } else {
+        Ok(Redirect::to(&url(issue_route)).into_response())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AssignParams {
+    /// see `ApiUpdate::assigned_to`
+    assigned_to: String,
+}
+
+pub async fn update_assignee(
+    route: IssueAssignee,
+    HxRequest(is_hx): HxRequest,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    token: SentryToken,
+    Form(params): Form<AssignParams>,
+) -> Result<impl IntoResponse, Error> {
+    let api = token.api()?;
+    let issue_route = IssueDetails::from(&route);
+    let api_update = ApiUpdate {
+        assigned_to: Some(params.assigned_to),
+        ..Default::default()
+    };
+    let issue = apply_update(
+        &token,
+        &api,
+        &issue_route,
+        connect_info,
+        &api_update,
+        ("assignee", |issue| {
+            issue
+                .assigned_to
+                .as_ref()
+                .map_or_else(|| "unassigned".to_owned(), ApiAssignee::display_name)
+        }),
+    )
+    .await?;
 
     if is_hx {
-        Ok(Html(render_button_status(&api_update.status)).into_response())
+        let assignees = assignee_choices(&api, &route.org, &route.proj, &issue.project.id).await;
+        Ok(Html(render_assignee(
+            route,
+            issue.assigned_to.as_ref(),
+            token.current_user(),
+            &assignees,
+        ))
+        .into_response())
     } else {
-        Ok(Redirect::to(
-            &IssueDetails {
-                org,
-                proj,
-                issue_id,
+        Ok(Redirect::to(&url(issue_route)).into_response())
+    }
+}
+
+/// Apply `api_update` to an issue and return the issue as Sentry has it afterwards. The change is
+/// recorded in the audit log, if there is one, as the old and new value of `field` according to
+/// `describe`.
+async fn apply_update(
+    token: &SentryToken,
+    api: &SentryApi,
+    route: &IssueDetails,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    api_update: &ApiUpdate,
+    (field, describe): (&str, fn(&ApiIssue) -> String),
+) -> Result<ApiIssue, Error> {
+    let audit_log = AuditLog::get();
+    // only fetched for the audit log. it may have been changed in Sentry since rendering the
+    // page, so the cached issue is not good enough.
+    let old_issue = match audit_log {
+        Some(_) => Some(
+            api.get_issue_revalidated(&route.org, &route.issue_id)
+                .await?,
+        ),
+        None => None,
+    };
+
+    api.update_issue(&route.org, &route.issue_id, api_update)
+        .await?;
+
+    // the cache has been invalidated by the update, so this is what sentry made of it. the page
+    // we redirect to without htmx is served from the cache then. the update went through even if
+    // this fails, so it's still recorded before the error is returned.
+    let new_issue = api.get_issue(&route.org, &route.issue_id).await;
+
+    if let (Some(audit_log), Some(old_issue)) = (audit_log, old_issue) {
+        audit_log
+            .record(&AuditEntry {
+                timestamp: Timestamp::now(),
+                upstream: api.upstream().base_url().to_owned(),
+                user: token.current_user().map(|user| user.email),
+                org: route.org.clone(),
+                project: route.proj.clone(),
+                issue_id: route.issue_id.clone(),
+                field: field.to_owned(),
+                old_value: describe(&old_issue),
+                new_value: match new_issue {
+                    Ok(ref new_issue) => describe(new_issue),
+                    Err(ref e) => {
+                        tracing::warn!("failed to fetch issue after updating it: {}", e);
+                        "unknown".to_owned()
+                    }
+                },
+                update: serde_json::to_value(api_update)?,
+                client_ip: connect_info.map(|ConnectInfo(addr)| addr.ip()),
+            })
+            .await?;
+    }
+
+    new_issue
+}
+
+/// Users and teams that an issue can be assigned to.
+#[derive(Default)]
+struct AssigneeChoices {
+    members: Vec<ApiUser>,
+    teams: Vec<ApiTeam>,
+}
+
+/// Failing to list these shouldn't break the issue page, the current assignee is still shown.
+async fn assignee_choices(
+    api: &SentryApi,
+    org: &str,
+    proj: &str,
+    project_id: &str,
+) -> AssigneeChoices {
+    let (members, teams) = tokio::join!(
+        api.list_members(org, project_id),
+        api.list_project_teams(org, proj)
+    );
+
+    fn or_empty<T>(result: Result<Vec<T>, Error>) -> Vec<T> {
+        result.unwrap_or_else(|e| {
+            tracing::debug!("failed to list possible assignees: {}", e);
+            Vec::new()
+        })
+    }
+
+    AssigneeChoices {
+        members: or_empty(members),
+        teams: or_empty(teams),
+    }
+}
+
+fn render_priority(route: IssuePriority, priority: &str) -> Markup {
+    let action = url(route);
+
+    html! {
+        div id="issue-priority" {
+            form
+                method="post"
+                action=(action)
+                hx-post=(action)
+                hx-target="#issue-priority"
+                hx-select="#issue-priority"
+                hx-swap="show:none"
+                data-busy-on-submit="" {
+
+                (csrf_field())
+                details.dropdown data-tooltip="change priority" {
+                    summary.outline.secondary role="button" {
+                        span data-priority=(priority) { (priority) } " priority"
+                    }
+
+                    ul {
+                        @for choice in ["high", "medium", "low"] {
+                            @if choice != priority {
+                                li { button.outline.secondary type="submit" name="priority" value=(choice) {
+                                    (choice)
+                                } }
+                            }
+                        }
+                    }
+                }
             }
-            .to_string(),
-        )
-        .into_response())
+        }
+    }
+}
+
+fn render_assignee(
+    route: IssueAssignee,
+    assigned_to: Option<&ApiAssignee>,
+    me: Option<ApiUser>,
+    choices: &AssigneeChoices,
+) -> Markup {
+    let action = url(route);
+    let current = assigned_to.map(ApiAssignee::actor_id);
+    // sessions from before the user ID was stored don't know it
+    let me = me
+        .map(|me| format!("user:{}", me.id))
+        .filter(|me| me != "user:" && Some(me) != current.as_ref());
+
+    html! {
+        div id="issue-assignee" {
+            form
+                method="post"
+                action=(action)
+                hx-post=(action)
+                hx-target="#issue-assignee"
+                hx-select="#issue-assignee"
+                hx-swap="show:none"
+                data-busy-on-submit="" {
+
+                (csrf_field())
+                details.dropdown data-tooltip="change assignee" {
+                    summary.outline.secondary role="button" {
+                        @if let Some(assignee) = assigned_to {
+                            "assigned to " (assignee.display_name())
+                        } @else {
+                            "unassigned"
+                        }
+                    }
+
+                    ul {
+                        @if let Some(ref me) = me {
+                            li { button type="submit" name="assigned_to" value=(me) {
+                                "assign to me"
+                            } }
+                        }
+                        @if assigned_to.is_some() {
+                            li { button.outline.secondary type="submit" name="assigned_to" value="" {
+                                "unassign"
+                            } }
+                        }
+
+                        @for member in &choices.members {
+                            @let value = format!("user:{}", member.id);
+                            @if Some(&value) != current.as_ref() {
+                                li { button.outline.secondary type="submit" name="assigned_to" value=(value) {
+                                    @if member.name.is_empty() { (member.email) } @else { (member.name) }
+                                } }
+                            }
+                        }
+
+                        @for team in &choices.teams {
+                            @let value = format!("team:{}", team.id);
+                            @if Some(&value) != current.as_ref() {
+                                li { button.outline.secondary type="submit" name="assigned_to" value=(value) {
+                                    "#" (team.slug)
+                                } }
+                            }
+                        }
+                    }
+                }
+            }
+        }
     }
 }
 
-fn render_button_status(status: &str) -> Markup {
+/// Releases and repositories to offer in the resolve dropdown.
+#[derive(Default)]
+struct ResolveChoices {
+    releases: Vec<ApiRelease>,
+    repositories: Vec<ApiRepository>,
+}
+
+/// Only needed while the issue is unresolved. Like `assignee_choices`, failures only leave out
+/// options.
+async fn resolve_choices(
+    api: &SentryApi,
+    org: &str,
+    proj: &str,
+    issue: &ApiIssue,
+) -> ResolveChoices {
+    if issue.status != "unresolved" {
+        return ResolveChoices::default();
+    }
+
+    let (releases, repositories) = tokio::join!(
+        api.list_recent_releases(org, proj),
+        api.list_repositories(org)
+    );
+
+    ResolveChoices {
+        releases: releases.unwrap_or_else(|e| {
+            tracing::debug!("failed to list releases: {}", e);
+            Vec::new()
+        }),
+        repositories: repositories.unwrap_or_else(|e| {
+            tracing::debug!("failed to list repositories: {}", e);
+            Vec::new()
+        }),
+    }
+}
+
+fn render_button_status(issue: &ApiIssue, choices: &ResolveChoices) -> Markup {
     let default_form = |content| {
         html! {
             form
@@ -660,8 +880,9 @@ fn render_button_status(status: &str) -> Markup {
                 hx-target="#issue-status"
                 hx-select="#issue-status"
                 hx-swap="show:none"
-                onsubmit="event.submitter.setAttribute('aria-busy', 'true')" {
+                data-busy-on-submit="" {
 
+                (csrf_field())
                 (content)
             }
         }
@@ -669,10 +890,8 @@ fn render_button_status(status: &str) -> Markup {
 
     html! {
         div id="issue-status" {
-            @match status {
+            @match issue.status.as_str() {
                 "unresolved" => div.grid {
-                    (detail_button_fixes())
-
                     (default_form(html! {
                         details.dropdown data-tooltip="change status to archived/ignored" {
 
@@ -688,12 +907,29 @@ fn render_button_status(status: &str) -> Markup {
                             li { button.outline.secondary type="submit" name="status" value="archived_forever" {
                                 "forever"
                             } }
+                            @for minutes in ARCHIVE_DURATIONS {
+                                li { button.outline.secondary type="submit" name="status" value=(format!("archived_for_minutes:{minutes}")) {
+                                    "for " (format_minutes(minutes))
+                                } }
+                            }
+                            @for count in ARCHIVE_THRESHOLDS {
+                                li { button.outline.secondary type="submit" name="status" value=(format!("archived_until_count:{count}")) {
+                                    "until " (count) " more events"
+                                } }
+                            }
+                            @for count in ARCHIVE_THRESHOLDS {
+                                li { button.outline.secondary type="submit" name="status" value=(format!("archived_until_user_count:{count}")) {
+                                    "until " (count) " more users are affected"
+                                } }
+                            }
 
                             }
                         }
                     }))
 
-                    (default_form(html! {
+                    // every option has its own form, so that pressing enter in the commit field
+                    // doesn't submit the first button instead
+                    div {
                         details.dropdown data-tooltip="change status to resolved" {
                             summary.outline role="button" {
                                 "resolve"
@@ -701,84 +937,334 @@ fn render_button_status(status: &str) -> Markup {
 
                             ul {
 
-                            li { button type="submit" name="status" value="resolved" {
-                                "globally"
-                            } }
+                            li { (default_form(html! {
+                                button type="submit" name="status" value="resolved" {
+                                    "globally"
+                                }
+                            })) }
 
-                            li { button.outline type="submit" name="status" value="resolved_in_next_release" {
-                                "in next release"
-                            } }
+                            li { (default_form(html! {
+                                button.outline type="submit" name="status" value="resolved_in_next_release" {
+                                    "in next release"
+                                }
+                            })) }
+
+                            @for release in &choices.releases {
+                                li { (default_form(html! {
+                                    button.outline type="submit" name="status" value=(format!("resolved_in_release:{}", release.version)) {
+                                        "in " code { (release.short_version.as_deref().unwrap_or(&release.version)) }
+                                    }
+                                })) }
+                            }
+
+                            @if !choices.repositories.is_empty() {
+                                li.resolve-in-commit { (default_form(html! {
+                                    // submitting with enter doesn't send the button's value
+                                    input type="hidden" name="status" value="resolved_in_commit";
+                                    input type="text" name="commit" placeholder="commit SHA" aria-label="commit SHA" autocomplete="off" required;
+                                    select name="repository" aria-label="repository" {
+                                        @for repository in &choices.repositories {
+                                            option { (repository.name) }
+                                        }
+                                    }
+                                    button.outline type="submit" {
+                                        "in this commit"
+                                    }
+                                })) }
+                            }
 
                             }
                         }
-                    }))
+                    }
                 },
-                "resolved" => (default_form(html! {
-                    (tooltip("change status to unresolved", html! {
-                        button type="submit" name="status" value="unresolved" title="issue is resolved. click to unresolve." {
-                            "resolved"
-                        }
+                "resolved" => {
+                    (default_form(html! {
+                        (tooltip("change status to unresolved", html! {
+                            button type="submit" name="status" value="unresolved" title="issue is resolved. click to unresolve." {
+                                "resolved"
+                            }
+                        }))
                     }))
-                })),
-                "ignored" => (default_form(html! {
-                    (tooltip("change status to unresolved", html! {
-                        button.secondary type="submit" name="status" value="unresolved" title="issue is archived/ignored. click to unresolve." {
-                            "archived"
-                        }
+                    @if let Some(reason) = resolve_reason(issue) {
+                        small.status-reason { (reason) }
+                    }
+                },
+                "ignored" => {
+                    (default_form(html! {
+                        (tooltip("change status to unresolved", html! {
+                            button.secondary type="submit" name="status" value="unresolved" title="issue is archived/ignored. click to unresolve." {
+                                "archived"
+                            }
+                        }))
                     }))
-                })),
+                    small.status-reason { (archive_reason(issue)) }
+                },
                 x => "unknown status: "(x)
             }
         }
     }
 }
 
+/// The status for the audit log, including how the issue is resolved or why it is archived.
+fn describe_status(issue: &ApiIssue) -> String {
+    match issue.status.as_str() {
+        "resolved" => match resolve_reason(issue) {
+            Some(reason) => format!("resolved {reason}"),
+            None => "resolved".to_owned(),
+        },
+        "ignored" => format!("archived {}", archive_reason(issue)),
+        status => status.to_owned(),
+    }
+}
+
+/// `None` if the issue is resolved right away.
+fn resolve_reason(issue: &ApiIssue) -> Option<String> {
+    let details = &issue.status_details;
+    if let Some(ref version) = details.in_release {
+        Some(format!("in release {version}"))
+    } else if details.in_next_release == Some(true) {
+        Some("in next release".to_owned())
+    } else {
+        let commit = details.in_commit.as_ref()?;
+        let sha = commit.id.get(..12).unwrap_or(&commit.id);
+        Some(match commit.repository {
+            Some(ref repository) => format!("in commit {sha} of {}", repository.name),
+            None => format!("in commit {sha}"),
+        })
+    }
+}
+
+fn archive_reason(issue: &ApiIssue) -> String {
+    let details = &issue.status_details;
+    let within = |window: Option<u32>| {
+        window
+            .map(|minutes| format!(" within {}", format_minutes(minutes)))
+            .unwrap_or_default()
+    };
+
+    match issue.substatus.as_deref() {
+        Some("archived_until_escalating") => "until escalating".to_owned(),
+        Some("archived_forever") => "forever".to_owned(),
+        // older instances don't send a substatus, but do send the conditions
+        _ => {
+            if let Some(until) = details.ignore_until {
+                format!("until {}", until.strftime("%Y-%m-%d %H:%M UTC"))
+            } else if let Some(count) = details.ignore_count {
+                format!("until {count} more events{}", within(details.ignore_window))
+            } else if let Some(count) = details.ignore_user_count {
+                format!(
+                    "until {count} more users are affected{}",
+                    within(details.ignore_user_window)
+                )
+            } else {
+                "forever".to_owned()
+            }
+        }
+    }
+}
+
+/// e.g. `2 hours`, for the durations in `ARCHIVE_DURATIONS` and the windows Sentry sends.
+fn format_minutes(minutes: u32) -> String {
+    let (amount, unit) = if minutes >= 24 * 60 && minutes.is_multiple_of(24 * 60) {
+        (minutes / (24 * 60), "day")
+    } else if minutes >= 60 && minutes.is_multiple_of(60) {
+        (minutes / 60, "hour")
+    } else {
+        (minutes, "minute")
+    };
+
+    if amount == 1 {
+        format!("1 {unit}")
+    } else {
+        format!("{amount} {unit}s")
+    }
+}
+
 // workaround to give tooltips to elements that can't have tooltips.
 // inputs can't have tooltips in picocss (due to limitations of ::before)
 // everything else can't have tooltips and loading indicators at the same time
 fn tooltip(tooltip: &str, content: Markup) -> Markup {
     html! {
-        div data-tooltip=(tooltip) style="border-bottom: none; cursor: inherit" {
+        div.tooltip data-tooltip=(tooltip) {
             (content)
         }
     }
 }
 
-// a bunch of picocss bugfixes for a button dropdown
-fn detail_button_fixes() -> Markup {
-    html! {
-        style {
-            r#"
-            :scope {
-                /* disagree with the decision to left-align button text if it's a dropdown. the
-                 * next two rules fix that */
-                details summary[role=button] {
-                    text-align: center;
-                }
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
 
-                details summary[role=button]::after {
-                    margin-left: -1rem;
-                }
+    use super::*;
 
-                /* bug in picocss: tooltips on detail elements show the help cursor */
-                details[data-tooltip] {
-                    cursor: inherit;
-                    border-bottom: none;
-                }
+    fn parse(form: &str) -> Result<UpdateParams, serde_urlencoded::de::Error> {
+        serde_urlencoded::from_str(form)
+    }
 
-                /* some weird padding issue */
-                details > ul > li > button {
-                    margin-bottom: 0;
-                }
+    fn api_update(form: &str) -> serde_json::Value {
+        let update = parse(form).unwrap().to_api().unwrap();
+        serde_json::to_value(update).unwrap()
+    }
 
-                /* shift dropdown to be anchored on the right, because the buttons are already
-                 * touching the edge of the screen */
-                details.dropdown[open] summary + ul {
-                    left: unset;
-                    right: 0;
-                }
-            }
-            "#
+    #[test]
+    fn test_status_to_api() {
+        assert_eq!(
+            api_update("status=unresolved"),
+            json!({"status": "unresolved"})
+        );
+        assert_eq!(api_update("status=resolved"), json!({"status": "resolved"}));
+        assert_eq!(
+            api_update("status=resolved_in_next_release"),
+            json!({"status": "resolved", "statusDetails": {"inNextRelease": true}})
+        );
+        assert_eq!(
+            api_update("status=archived_until_escalating"),
+            json!({"status": "ignored", "substatus": "archived_until_escalating"})
+        );
+        assert_eq!(
+            api_update("status=archived_forever"),
+            json!({"status": "ignored", "substatus": "archived_forever"})
+        );
+    }
+
+    #[test]
+    fn test_resolve_in_release_to_api() {
+        assert_eq!(
+            api_update("status=resolved_in_release:backend@1.0"),
+            json!({"status": "resolved", "statusDetails": {"inRelease": "backend@1.0"}})
+        );
+        // only the first colon separates the status from the version
+        assert_eq!(
+            api_update("status=resolved_in_release:pkg@1.0:beta"),
+            json!({"status": "resolved", "statusDetails": {"inRelease": "pkg@1.0:beta"}})
+        );
+        assert!(parse("status=resolved_in_release:").is_err());
+        assert!(parse("status=resolved_in_release").is_err());
+    }
+
+    #[test]
+    fn test_resolve_in_commit_to_api() {
+        assert_eq!(
+            api_update("status=resolved_in_commit&commit=+abc123+&repository=getsentry%2Fsentry"),
+            json!({
+                "status": "resolved",
+                "statusDetails": {
+                    "inCommit": {"commit": "abc123", "repository": "getsentry/sentry"},
+                },
+            })
+        );
+
+        for form in [
+            "status=resolved_in_commit&repository=getsentry%2Fsentry",
+            "status=resolved_in_commit&commit=+&repository=getsentry%2Fsentry",
+            "status=resolved_in_commit&commit=abc123",
+            "status=resolved_in_commit&commit=abc123&repository=",
+        ] {
+            let result = parse(form).unwrap().to_api();
+            assert!(
+                matches!(result, Err(Error::InvalidForm(_))),
+                "{form} was accepted"
+            );
         }
     }
+
+    fn issue_with_details(status_details: serde_json::Value) -> ApiIssue {
+        serde_json::from_value(json!({
+            "id": "1",
+            "title": "ZeroDivisionError",
+            "firstSeen": "2024-01-01T00:00:00Z",
+            "lastSeen": "2024-01-02T00:00:00Z",
+            "status": "resolved",
+            "statusDetails": status_details,
+            "level": "error",
+            "permalink": "https://sentry.example.com/issues/1/",
+            "shortId": "PROJ-1",
+            "count": "1",
+            "project": {"id": "1"},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolve_reason() {
+        let reason = |details| resolve_reason(&issue_with_details(details));
+        assert_eq!(reason(json!({})), None);
+        assert_eq!(
+            reason(json!({"inRelease": "pkg@1.0:beta"})).unwrap(),
+            "in release pkg@1.0:beta"
+        );
+        assert_eq!(
+            reason(json!({"inNextRelease": true})).unwrap(),
+            "in next release"
+        );
+        assert_eq!(
+            reason(json!({
+                "inCommit": {
+                    "id": "0123456789abcdef0123",
+                    "repository": {"name": "getsentry/sentry"},
+                },
+            }))
+            .unwrap(),
+            "in commit 0123456789ab of getsentry/sentry"
+        );
+        assert_eq!(
+            reason(json!({"inCommit": {"id": "abc123"}})).unwrap(),
+            "in commit abc123"
+        );
+    }
+
+    #[test]
+    fn test_archive_thresholds_to_api() {
+        assert_eq!(
+            api_update("status=archived_for_minutes:30"),
+            json!({
+                "status": "ignored",
+                "substatus": "archived_until_condition_met",
+                "statusDetails": {"ignoreDuration": 30},
+            })
+        );
+        assert_eq!(
+            api_update("status=archived_until_count:100"),
+            json!({
+                "status": "ignored",
+                "substatus": "archived_until_condition_met",
+                "statusDetails": {"ignoreCount": 100},
+            })
+        );
+        assert_eq!(
+            api_update("status=archived_until_user_count:10"),
+            json!({
+                "status": "ignored",
+                "substatus": "archived_until_condition_met",
+                "statusDetails": {"ignoreUserCount": 10},
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_status() {
+        for form in [
+            "status=archived_for_minutes:0",
+            "status=archived_until_count:0",
+            "status=archived_until_user_count:0",
+            "status=archived_for_minutes:-5",
+            "status=archived_for_minutes:soon",
+            "status=archived_until_count:",
+            "status=archived_until_count",
+            "status=archived_forever:10",
+            "status=deleted",
+            "status=",
+        ] {
+            assert!(parse(form).is_err(), "{form} was accepted");
+        }
+    }
+
+    #[test]
+    fn test_format_minutes() {
+        assert_eq!(format_minutes(30), "30 minutes");
+        assert_eq!(format_minutes(60), "1 hour");
+        assert_eq!(format_minutes(90), "90 minutes");
+        assert_eq!(format_minutes(24 * 60), "1 day");
+        assert_eq!(format_minutes(7 * 24 * 60), "7 days");
+    }
 }
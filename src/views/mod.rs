@@ -1,6 +1,6 @@
-mod helpers;
-
+pub mod audit_log;
 pub mod auth;
+pub mod helpers;
 pub mod index;
 pub mod issue_details;
 pub mod organization_details;
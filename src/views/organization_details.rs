@@ -1,53 +1,39 @@
 use crate::views::helpers::html;
 use axum::response::IntoResponse;
-use serde::Deserialize;
 
+use crate::routes::{url, ProjectDetails};
 use crate::views::helpers::{breadcrumbs, wrap_admin_template, Html, LayoutOptions};
 use crate::{Error, SentryToken};
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ApiProject {
-    name: String,
-    slug: String,
-    #[serde(default)]
-    is_bookmarked: bool,
-}
-
 pub async fn organization_details(
     route: crate::routes::OrganizationDetails,
     token: SentryToken,
 ) -> Result<impl IntoResponse, Error> {
     let org = route.org;
 
-    let client = token.client()?;
-    let mut response: Vec<ApiProject> = client
-        .get(format!(
-            "https://sentry.io/api/0/organizations/{org}/projects/"
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
+    let api = token.api()?;
+    let mut response = api.list_projects(&org).await?;
 
     response.sort_by_key(|p| !p.is_bookmarked);
 
     let body = wrap_admin_template(
         LayoutOptions {
             title: org.clone(),
+            rate_limit: api.low_rate_limit(),
+            accounts: token.accounts(),
             ..Default::default()
         },
         html! {
-            (breadcrumbs(&format!("https://sentry.io/{}", org), html! {
-                (org) ": projects"
+            (breadcrumbs(&api.upstream().web_url(&format!("organizations/{org}/projects/")), html! {
+                (org) ": projects "
+                small.secondary { "(" (response.len()) ")" }
             }))
 
             ul {
                 @for project in response {
                     li {
                         a preload="mouseover" href=(
-                            crate::routes::ProjectDetails { org: org.clone(), proj: project.slug.clone() }
+                            url(ProjectDetails { org: org.clone(), proj: project.slug.clone() })
                         ) {
                             (project.name)
                         }
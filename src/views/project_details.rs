@@ -1,39 +1,21 @@
 use axum::extract::Query;
 use axum::response::IntoResponse;
-use jiff::Timestamp;
 use maud::Markup;
 use serde::Deserialize;
 
-use crate::routes::{IssueDetails, OrganizationDetails, ProjectDetails};
+use crate::routes::{url, IssueDetails, OrganizationDetails, ProjectDetails};
+use crate::sentry_api::{ApiIssue, Page};
 use crate::views::helpers::{
     breadcrumbs, event_count, html, print_relative_time, wrap_admin_template, Html, LayoutOptions,
 };
 use crate::{Error, SentryToken};
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ApiIssue {
-    title: String,
-    culprit: String,
-    last_seen: Timestamp,
-    level: String,
-    id: String,
-    #[serde(default)]
-    logger: Option<String>,
-    count: String,
-    project: ApiProject,
-}
-
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct ApiProject {
-    id: String,
-}
-
 #[derive(Deserialize)]
 pub struct SearchQuery {
     #[serde(default)]
     query: Option<String>,
+    #[serde(default)]
+    cursor: Option<String>,
 }
 
 pub async fn project_details(
@@ -44,23 +26,17 @@ pub async fn project_details(
     let org = route.org;
     let proj = route.proj;
 
-    let client = token.client()?;
+    let api = token.api()?;
     let query = params
         .query
         .as_deref()
         .unwrap_or("is:unresolved issue.priority:[high, medium]");
-    let response: Vec<ApiIssue> = client
-        .get(format!(
-            "https://sentry.io/api/0/projects/{org}/{proj}/issues/"
-        ))
-        .query(&[("query", query), ("limit", "25")])
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
+    let page = api
+        .list_issues(&org, &proj, query, params.cursor.as_deref())
         .await?;
 
-    let project_id = response
+    let project_id = page
+        .items
         .first()
         .map(|x| x.project.id.as_str())
         .unwrap_or("");
@@ -68,52 +44,78 @@ pub async fn project_details(
     let body = wrap_admin_template(
         LayoutOptions {
             title: format!("{org}/{proj}"),
+            rate_limit: api.low_rate_limit(),
+            accounts: token.accounts(),
             ..Default::default()
         },
         html! {
-            (breadcrumbs(&format!("https://sentry.io/issues/?project={project_id}&query={query}&statsPeriod=24h"), html! {
-                a preload="mouseover" href=(OrganizationDetails { org: org.clone() }) { (org) }
+            (breadcrumbs(&api.upstream().web_url(&format!("organizations/{org}/issues/?project={project_id}&query={query}&statsPeriod=24h")), html! {
+                a preload="mouseover" href=(url(OrganizationDetails { org: org.clone() })) { (org) }
                 (format!("/{proj}"))
                 ": issues"
             }))
 
-            style {
-                r#"
-                .issue-row {
-                    padding: calc(var(--pico-spacing)/ 2) var(--pico-spacing);
-                    margin-bottom: 0;
-                    border-bottom: var(--pico-border-width) solid var(--pico-table-border-color);
-                }
-
-                .issue-row a {
-                    text-decoration: none;
-                }
-
-                code {
-                    word-wrap: anywhere;
-                }
-                "#
-            }
-
-            form method="get" action=(ProjectDetails { org: org.clone(), proj: proj.clone()}) {
+            form method="get" action=(url(ProjectDetails { org: org.clone(), proj: proj.clone()})) {
                 fieldset role="group" {
                     input type="text" name="query" value=(query);
                     input type="submit" value="filter issues";
                 }
             }
 
-            (render_issuestream(&org, &proj, &response))
+            (render_issuestream(&org, &proj, query, &page))
         },
     );
 
     Ok(Html(body))
 }
 
-fn render_issuestream(org: &str, proj: &str, response: &[ApiIssue]) -> Markup {
+fn render_issuestream(org: &str, proj: &str, query: &str, page: &Page<ApiIssue>) -> Markup {
+    let page_url = |cursor: &str| {
+        let params = serde_urlencoded::to_string([("query", query), ("cursor", cursor)]).unwrap();
+        format!(
+            "{}?{params}",
+            url(ProjectDetails {
+                org: org.to_owned(),
+                proj: proj.to_owned()
+            })
+        )
+    };
+
+    html! {
+        div #issue-stream {
+            (render_issue_rows(org, proj, &page.items))
+
+            nav.pagination {
+                ul {
+                    @if let Some(ref cursor) = page.prev_cursor {
+                        li { a.secondary href=(page_url(cursor)) { "previous page" } }
+                    }
+                }
+                ul {
+                    @if let Some(ref cursor) = page.next_cursor {
+                        // with htmx, append the next page to this one instead of navigating.
+                        // the new nav replaces this one so that the next "load more" picks up
+                        // the new cursor.
+                        li { a.secondary
+                            href=(page_url(cursor))
+                            hx-get=(page_url(cursor))
+                            hx-select="#issue-stream > .issue-row, #issue-stream > nav.pagination"
+                            hx-target="closest nav"
+                            hx-swap="outerHTML"
+                            { "load more" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn render_issue_rows(org: &str, proj: &str, response: &[ApiIssue]) -> Markup {
     html! {
         @for issue in response {
             div.issue-row {
-                a preload="mouseover" href=(IssueDetails { org: org.to_owned(), proj: proj.to_owned(), issue_id: issue.id.clone() }) {
+                a preload="mouseover" href=(url(IssueDetails { org: org.to_owned(), proj: proj.to_owned(), issue_id: issue.id.clone() })) {
                     span data-level=(issue.level) { (issue.level) ": "}
                     (issue.title)
 
@@ -132,6 +134,14 @@ fn render_issuestream(org: &str, proj: &str, response: &[ApiIssue]) -> Markup {
                             ", logged via "
                             code { (logger) }
                         }
+
+                        @if let Some(ref priority) = issue.priority {
+                            ", " span data-priority=(priority) { (priority) } " priority"
+                        }
+
+                        @if let Some(ref assignee) = issue.assigned_to {
+                            ", assigned to " (assignee.display_name())
+                        }
                     }
                 }
             }
@@ -0,0 +1,9 @@
+// Small progressive enhancements. Kept out of the HTML so that the Content-Security-Policy can
+// forbid inline scripts.
+
+// show a spinner on the button that submitted a form
+document.addEventListener("submit", function (event) {
+    if (event.target.hasAttribute("data-busy-on-submit") && event.submitter) {
+        event.submitter.setAttribute("aria-busy", "true");
+    }
+});